@@ -0,0 +1,417 @@
+//! Reading archives as trees.
+//!
+//! `ArchiveTree` reads the entries of a tar or zip archive, with their sizes, modes and mtimes
+//! from the archive headers, and can be walked by `TreeIter` like a directory on disk. File
+//! contents are never extracted, although compressed tarballs are decompressed in a streaming
+//! fashion to get at the headers. The exception is archives within archives, which are read into
+//! memory to be expanded as well, up to a given nesting depth.
+
+extern crate flate2;
+extern crate tar;
+extern crate zip;
+extern crate zstd;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use self::flate2::read::GzDecoder;
+use self::tar::EntryType;
+use super::metadata::{FileType, Metadata};
+use super::replay::{Node, NodeInfo, NodeSource};
+
+/// The mode of directories that are implied by the paths of other entries, but have no entry of
+/// their own.
+const IMPLICIT_DIR_MODE: u32 = 0o755;
+
+/// File type bits of a unix mode, and the value for symlinks.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A supported archive format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    /// Tar compressed with gzip.
+    TarGz,
+    /// Tar compressed with Zstandard.
+    TarZst,
+    /// Zip, including formats based on it such as jar.
+    Zip,
+}
+
+impl Format {
+    /// Guess the format of the archive at `path` from its file name.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_lowercase(),
+            None => return None,
+        };
+
+        if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else if name.ends_with(".zip") || name.ends_with(".jar") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A tree read from an archive.
+///
+/// The entries of each directory are sorted by name. Directories without an entry of their own
+/// in the archive are included with default metadata, and when an archive holds several entries
+/// with the same path, the last one wins, as when extracting it.
+pub struct ArchiveTree {
+    root: PathBuf,
+    source: NodeSource,
+}
+
+impl ArchiveTree {
+    /// Read the archive at `path`, in `format`. Entry paths are prefixed with `path`.
+    ///
+    /// Archives within the archive are expanded up to `nesting` levels deep, see
+    /// `read_archive()`.
+    pub fn open(path: &Path, format: Format, nesting: usize) -> Result<Self, Box<Error>> {
+        ArchiveTree::from_reader(try!(File::open(path)), path.to_path_buf(), format, nesting)
+    }
+
+    /// Read an archive in `format` from `reader`, with entry paths prefixed by `root`.
+    pub fn from_reader<R>(reader: R,
+                          root: PathBuf,
+                          format: Format,
+                          nesting: usize)
+                          -> Result<Self, Box<Error>>
+        where R: Read + Seek
+    {
+        let contents = try!(read_archive(BufReader::new(reader), format, nesting));
+
+        Ok(ArchiveTree {
+            source: try!(NodeSource::new(root.clone(), contents)),
+            root: root,
+        })
+    }
+
+    /// The path of the archive, as given when it was opened.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The tree as a source for `TreeIter`.
+    pub fn into_source(self) -> NodeSource {
+        self.source
+    }
+}
+
+/// Read the entries of an archive in `format` from `reader`.
+///
+/// While `nesting` is above zero, members named like archives are read into memory and expanded
+/// as directories holding their own entries, with `nesting` decreased by one. Members that turn
+/// out not to be readable archives are kept as files.
+pub fn read_archive<R>(reader: R, format: Format, nesting: usize) -> Result<Vec<Node>, Box<Error>>
+    where R: BufRead + Seek
+{
+    match format {
+        Format::Tar => read_tar(reader, nesting),
+        Format::TarGz => read_tar(GzDecoder::new(reader), nesting),
+        Format::TarZst => read_tar(try!(zstd::stream::read::Decoder::with_buffer(reader)), nesting),
+        Format::Zip => read_zip(reader, nesting),
+    }
+}
+
+/// Read the entries of an uncompressed tar archive.
+fn read_tar<R: Read>(reader: R, nesting: usize) -> Result<Vec<Node>, Box<Error>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut builder = DirBuilder::default();
+
+    for entry in try!(archive.entries()) {
+        let mut entry = try!(entry);
+
+        match entry.header().entry_type() {
+            // Records describing other entries, or the archive itself, like the comment holding
+            // the commit id in the output of `git archive`
+            EntryType::XGlobalHeader | EntryType::XHeader | EntryType::GNULongName |
+            EntryType::GNULongLink => continue,
+            _ => {},
+        }
+
+        let metadata = {
+            let header = entry.header();
+
+            let file_type = match header.entry_type() {
+                EntryType::Directory => FileType::Dir,
+                EntryType::Symlink => FileType::Symlink,
+                EntryType::Fifo => FileType::Fifo,
+                EntryType::Block => FileType::BlockDevice,
+                EntryType::Char => FileType::CharDevice,
+                // Including hard links, which are regular files when extracted
+                _ => FileType::File,
+            };
+
+            let mut metadata = Metadata::new(file_type);
+            metadata.len = try!(header.size());
+            metadata.mode = try!(header.mode()) & 0o7777;
+            metadata.mtime = try!(header.mtime()) as i64;
+            metadata.uid = try!(header.uid()) as u32;
+            metadata.gid = try!(header.gid()) as u32;
+            metadata
+        };
+
+        let components = components(&try!(entry.path()));
+        let nested = if nesting > 0 && metadata.is_file() {
+            read_nested(&mut entry, &components, nesting - 1)
+        } else {
+            None
+        };
+
+        builder.insert(&components, metadata, nested);
+    }
+
+    Ok(builder.into_nodes())
+}
+
+/// Read the entries of a zip archive.
+///
+/// Only the central directory is read, unless there are nested archives to expand. Zip archives
+/// store mtimes in local time without a time zone, so they're taken to be in UTC.
+fn read_zip<R: Read + Seek>(reader: R, nesting: usize) -> Result<Vec<Node>, Box<Error>> {
+    let mut archive = try!(zip::ZipArchive::new(reader));
+    let mut builder = DirBuilder::default();
+
+    for i in 0..archive.len() {
+        let (components, metadata) = {
+            let file = try!(archive.by_index_raw(i));
+            let mode = file.unix_mode();
+
+            let file_type = if file.is_dir() {
+                FileType::Dir
+            } else if mode.map_or(false, |mode| mode & S_IFMT == S_IFLNK) {
+                FileType::Symlink
+            } else {
+                FileType::File
+            };
+
+            let mut metadata = Metadata::new(file_type);
+            metadata.len = file.size();
+            metadata.mode = match mode {
+                Some(mode) => mode & 0o7777,
+                None if file.is_dir() => IMPLICIT_DIR_MODE,
+                None => 0o644,
+            };
+
+            let time = file.last_modified();
+            metadata.mtime = unix_time(time.year() as i64,
+                                       time.month() as i64,
+                                       time.day() as i64,
+                                       time.hour() as i64 * 3600 + time.minute() as i64 * 60 +
+                                       time.second() as i64);
+
+            (components(Path::new(file.name())), metadata)
+        };
+
+        let nested = if nesting > 0 && metadata.is_file() {
+            match archive.by_index(i) {
+                Ok(mut file) => read_nested(&mut file, &components, nesting - 1),
+                // E.g. encrypted or compressed with an unsupported method
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        builder.insert(&components, metadata, nested);
+    }
+
+    Ok(builder.into_nodes())
+}
+
+/// The entries of `member`, if the last of its path `components` names an archive that can be
+/// read. The member is read into memory, since nested zip archives need to be seekable.
+fn read_nested<R>(member: &mut R, components: &[String], nesting: usize) -> Option<Vec<Node>>
+    where R: Read
+{
+    let format = match components.last().and_then(|name| Format::from_path(Path::new(name))) {
+        Some(format) => format,
+        None => return None,
+    };
+
+    let mut data = Vec::new();
+
+    if member.read_to_end(&mut data).is_err() {
+        return None;
+    }
+
+    read_archive(Cursor::new(data), format, nesting).ok()
+}
+
+/// A directory being built from archive entries, which may come in any order.
+#[derive(Default)]
+struct DirBuilder {
+    /// The metadata from the directory's own entry, if it has one.
+    metadata: Option<Metadata>,
+    children: BTreeMap<String, Child>,
+}
+
+enum Child {
+    Dir(DirBuilder),
+    /// An archive member that is an archive itself, with its entries.
+    Archive(Metadata, Vec<Node>),
+    Other(Metadata),
+}
+
+impl DirBuilder {
+    /// Add the entry at the relative path made up of `components`, creating any missing
+    /// directories along the way. `nested` holds the entries of a file that is an archive.
+    fn insert(&mut self, components: &[String], metadata: Metadata, nested: Option<Vec<Node>>) {
+        let (name, rest) = match components.split_first() {
+            Some(split) => split,
+            // The root itself, e.g. `./`
+            None => return,
+        };
+
+        if rest.is_empty() && !metadata.is_dir() {
+            let child = match nested {
+                Some(nodes) => Child::Archive(metadata, nodes),
+                None => Child::Other(metadata),
+            };
+            self.children.insert(name.clone(), child);
+            return;
+        }
+
+        let child = self.children
+            .entry(name.clone())
+            .or_insert_with(|| Child::Dir(DirBuilder::default()));
+
+        // Entries below a file turn it into a directory
+        match *child {
+            Child::Dir(_) => {},
+            _ => *child = Child::Dir(DirBuilder::default()),
+        }
+
+        if let Child::Dir(ref mut dir) = *child {
+            if rest.is_empty() {
+                dir.metadata = Some(metadata);
+            } else {
+                dir.insert(rest, metadata, nested);
+            }
+        }
+    }
+
+    /// The nodes of the directory's children, all marked as archive members.
+    fn into_nodes(self) -> Vec<Node> {
+        self.children
+            .into_iter()
+            .map(|(name, child)| {
+                let member = |metadata| {
+                    let mut info = NodeInfo::new(name, metadata);
+                    info.archive_member = true;
+                    info
+                };
+
+                match child {
+                    Child::Dir(dir) => {
+                        let metadata = dir.metadata.clone().unwrap_or_else(|| {
+                            let mut metadata = Metadata::new(FileType::Dir);
+                            metadata.mode = IMPLICIT_DIR_MODE;
+                            metadata
+                        });
+
+                        Node::Dir(member(metadata), dir.into_nodes())
+                    },
+                    Child::Archive(metadata, nodes) => Node::Dir(member(metadata), nodes),
+                    Child::Other(metadata) => Node::File(member(metadata)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The names making up a relative path in an archive. Components like `.` are skipped, and so
+/// are `..` and leading slashes, which extraction tools refuse or strip.
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Seconds since the epoch of `seconds` into the given day in UTC.
+fn unix_time(year: i64, month: i64, day: i64, seconds: i64) -> i64 {
+    // Days since the epoch of a date in the proleptic Gregorian calendar, counting years from
+    // March so that leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    days * 86400 + seconds
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use super::*;
+    use super::super::tree::{Event, TreeIter};
+
+    fn include_all(_path: &Path, _metadata: &Metadata) -> Result<bool, Box<Error>> {
+        Ok(true)
+    }
+
+    /// The paths of the entries in `archive`, relative to the archive.
+    fn paths(archive: Vec<u8>) -> Vec<String> {
+        let tree = ArchiveTree::from_reader(Cursor::new(archive),
+                                            PathBuf::from("archive.tar"),
+                                            Format::Tar,
+                                            0)
+            .unwrap();
+
+        TreeIter::with_source(tree.into_source(), "archive.tar", include_all)
+            .unwrap()
+            .filter_map(|event| match event.unwrap() {
+                Event::File(entry) | Event::OpenDir(entry) => {
+                    Some(entry.path().strip_prefix("archive.tar").unwrap().display().to_string())
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pax_headers_are_skipped() {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        // Like the first entry written by `git archive`
+        let comment = b"52 comment=0123456789abcdef0123456789abcdef01234567\n";
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(EntryType::XGlobalHeader);
+        header.set_path("pax_global_header").unwrap();
+        header.set_size(comment.len() as u64);
+        header.set_mode(0o666);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append(&header, &comment[..]).unwrap();
+
+        let mut header = tar::Header::new_ustar();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder.append_data(&mut header, "src/main.rs", &b"data"[..]).unwrap();
+
+        assert_eq!(paths(builder.into_inner().unwrap()), ["src", "src/main.rs"]);
+    }
+}
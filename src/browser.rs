@@ -0,0 +1,540 @@
+//! An interactive terminal browser for trees.
+//!
+//! `Browser` draws on the controlling terminal rather than stdout, so that stdout is free for the
+//! selected path, e.g. `cd $(oak -i)`. Directories are read lazily using `TreeIter`, when they're
+//! first expanded.
+
+extern crate libc;
+extern crate termion;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use self::termion::event::Key;
+use self::termion::input::TermRead;
+use self::termion::raw::IntoRawMode;
+use self::termion::screen::IntoAlternateScreen;
+use self::termion::{clear, cursor, style};
+use super::filters::FilterAggregate;
+use super::print_processor::tree_prefix;
+use super::tree::{Entry, Event, ReadDirError, TreeIter};
+
+/// Help shown in the status line.
+const KEYS_HELP: &'static str = "←→ collapse/expand  / search  . hidden  i gitignore  \
+                                 enter select  q quit";
+
+/// The filters that can be toggled while browsing.
+#[derive(Clone, Copy, Debug)]
+pub struct FilterToggles {
+    /// Include hidden files.
+    pub show_hidden: bool,
+    /// Apply gitignore rules.
+    pub gitignore: bool,
+}
+
+/// A line of the browser, which is an entry of the tree.
+struct Row {
+    path: PathBuf,
+    name: String,
+    is_dir: bool,
+    ignored: bool,
+    /// Depth in the tree, where the root is at depth 0.
+    depth: usize,
+    expanded: bool,
+    /// Why the directory can't be read, if it can't. Such rows can't be expanded.
+    unreadable: Option<String>,
+    /// Whether the directory is on another file system than the root, which isn't read when
+    /// staying on one. Such rows can't be expanded.
+    mount_point: bool,
+}
+
+impl Row {
+    fn from_entry(entry: &Entry, depth: usize) -> Self {
+        Row {
+            path: entry.path().to_path_buf(),
+            name: entry.file_name().into_owned(),
+            is_dir: entry.metadata().is_dir(),
+            ignored: entry.is_ignored(),
+            depth: depth,
+            expanded: false,
+            unreadable: None,
+            mount_point: entry.is_mount_point(),
+        }
+    }
+
+    /// A row for a directory whose entries couldn't be read.
+    fn unreadable(err: &ReadDirError, depth: usize) -> Self {
+        Row {
+            path: err.path().to_path_buf(),
+            name: err.path().file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            is_dir: true,
+            ignored: false,
+            depth: depth,
+            expanded: false,
+            unreadable: Some(err.to_string()),
+            mount_point: false,
+        }
+    }
+}
+
+/// An incremental search being typed.
+struct Search {
+    query: String,
+    /// The selection when the search started, restored if it's cancelled.
+    origin: usize,
+}
+
+/// An interactive browser for the tree rooted at a directory.
+///
+/// Directories are expanded and collapsed with the arrow keys, and searched incrementally by name
+/// with `/`, among the entries that have been read so far. Hidden files and gitignore rules are
+/// toggled with `.` and `i`. Enter selects the current entry and quits.
+pub struct Browser<'a> {
+    root: PathBuf,
+    toggles: FilterToggles,
+    /// Builds the filters for the current toggles.
+    make_filters: Box<Fn(&FilterToggles) -> FilterAggregate + 'a>,
+    rows: Vec<Row>,
+    selected: usize,
+    /// Index of the first row on screen.
+    scroll: usize,
+    search: Option<Search>,
+    /// The last search, repeated with `n`.
+    last_query: String,
+    /// An error to show in the status line.
+    message: Option<String>,
+    one_file_system: bool,
+}
+
+impl<'a> Browser<'a> {
+    /// Create a browser for the tree rooted at `root`, which is read and expanded once it runs.
+    ///
+    /// `make_filters` is called to build the filters whenever a directory is read.
+    pub fn new<F>(root: &Path, toggles: FilterToggles, make_filters: F) -> Self
+        where F: Fn(&FilterToggles) -> FilterAggregate + 'a
+    {
+        Browser {
+            root: root.to_path_buf(),
+            toggles: toggles,
+            make_filters: Box::new(make_filters),
+            rows: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            search: None,
+            last_query: String::new(),
+            message: None,
+            one_file_system: false,
+        }
+    }
+
+    /// Do not expand directories on other file systems than the root, which are marked as mount
+    /// points instead, see `TreeIter::one_file_system()`.
+    pub fn one_file_system(&mut self, one_file_system: bool) -> &mut Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Run the browser until the user quits, returning the selected path if one was selected.
+    pub fn run(&mut self) -> Result<Option<PathBuf>, Box<Error>> {
+        // Fail before taking over the terminal, e.g. if the root can't be read
+        if self.rows.is_empty() {
+            try!(self.reload());
+        }
+
+        let input = try!(termion::get_tty());
+        let output = try!(termion::get_tty());
+        let fd = output.as_raw_fd();
+        let mut screen = try!(try!(output.into_raw_mode()).into_alternate_screen());
+
+        try!(write!(screen, "{}", cursor::Hide));
+        let result = self.browse(&mut screen, input, fd);
+        // Leaving the alternate screen doesn't show the cursor again
+        try!(write!(screen, "{}", cursor::Show));
+        try!(screen.flush());
+
+        result
+    }
+
+    /// Draw and handle keys until the user quits.
+    fn browse<W: Write>(&mut self,
+                        screen: &mut W,
+                        input: File,
+                        fd: RawFd)
+                        -> Result<Option<PathBuf>, Box<Error>> {
+        let mut keys = input.keys();
+
+        loop {
+            let (width, height) = terminal_size(fd);
+            try!(self.draw(screen, width as usize, height as usize));
+
+            let key = match keys.next() {
+                Some(key) => try!(key),
+                None => return Ok(None),
+            };
+
+            self.message = None;
+
+            if self.search.is_some() {
+                self.search_key(key);
+                continue;
+            }
+
+            match key {
+                Key::Char('\n') => {
+                    return Ok(self.rows.get(self.selected).map(|row| row.path.clone()))
+                },
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => return Ok(None),
+                key => self.key(key, height as usize),
+            }
+        }
+    }
+
+    /// Handle a key outside of searching.
+    fn key(&mut self, key: Key, height: usize) {
+        let page = height.saturating_sub(2).max(1);
+        let last = self.rows.len().saturating_sub(1);
+
+        match key {
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected = (self.selected + 1).min(last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(page),
+            Key::PageDown => self.selected = (self.selected + page).min(last),
+            Key::Home | Key::Char('g') => self.selected = 0,
+            Key::End | Key::Char('G') => self.selected = last,
+            Key::Right | Key::Char('l') => {
+                let selected = self.selected;
+
+                if self.rows[selected].expanded {
+                    self.selected = (selected + 1).min(last);
+                } else {
+                    self.report(|browser| browser.expand(selected));
+                }
+            },
+            Key::Left | Key::Char('h') => {
+                if self.rows[self.selected].expanded {
+                    let selected = self.selected;
+                    self.collapse(selected);
+                } else {
+                    self.selected = self.parent(self.selected);
+                }
+            },
+            Key::Char(' ') => {
+                let selected = self.selected;
+
+                if self.rows[selected].expanded {
+                    self.collapse(selected);
+                } else {
+                    self.report(|browser| browser.expand(selected));
+                }
+            },
+            Key::Char('.') => {
+                self.toggles.show_hidden = !self.toggles.show_hidden;
+                self.report(Browser::reload);
+            },
+            Key::Char('i') => {
+                self.toggles.gitignore = !self.toggles.gitignore;
+                self.report(Browser::reload);
+            },
+            Key::Char('/') => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    origin: self.selected,
+                });
+            },
+            Key::Char('n') => {
+                let start = self.selected + 1;
+                let query = self.last_query.clone();
+
+                if let Some(found) = self.find(&query, start) {
+                    self.selected = found;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Handle a key while searching.
+    fn search_key(&mut self, key: Key) {
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+
+        match key {
+            Key::Char('\n') => {
+                self.last_query = search.query;
+                return;
+            },
+            Key::Esc | Key::Ctrl('c') => {
+                self.selected = search.origin;
+                return;
+            },
+            Key::Backspace => {
+                search.query.pop();
+            },
+            Key::Char(c) => search.query.push(c),
+            _ => {},
+        }
+
+        self.selected = self.find(&search.query, search.origin).unwrap_or(search.origin);
+        self.search = Some(search);
+    }
+
+    /// The first row from `start` on, wrapping around, with a name containing `query` ignoring
+    /// case.
+    fn find(&self, query: &str, start: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        let len = self.rows.len();
+
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|&i| self.rows[i].name.to_lowercase().contains(&query))
+    }
+
+    /// Run `action`, showing any error in the status line.
+    fn report<F>(&mut self, action: F)
+        where F: FnOnce(&mut Self) -> Result<(), Box<Error>>
+    {
+        if let Err(err) = action(self) {
+            self.message = Some(err.to_string());
+        }
+    }
+
+    /// Read the entries of the directory at row `index` and show them below it.
+    fn expand(&mut self, index: usize) -> Result<(), Box<Error>> {
+        if !self.rows[index].is_dir || self.rows[index].expanded {
+            return Ok(());
+        }
+
+        if let Some(ref err) = self.rows[index].unreadable {
+            return Err(From::from(err.as_str()));
+        }
+
+        if self.rows[index].mount_point {
+            return Err(From::from(format!("'{}' is on another file system",
+                                          self.rows[index].path.display())));
+        }
+
+        let children = try!(self.read_dir(&self.rows[index].path, self.rows[index].depth + 1));
+        self.rows[index].expanded = true;
+
+        let tail = self.rows.split_off(index + 1);
+        self.rows.extend(children);
+        self.rows.extend(tail);
+
+        Ok(())
+    }
+
+    /// Hide the entries below the directory at row `index`.
+    fn collapse(&mut self, index: usize) {
+        let depth = self.rows[index].depth;
+        let end = self.rows[index + 1..]
+            .iter()
+            .position(|row| row.depth <= depth)
+            .map_or(self.rows.len(), |position| index + 1 + position);
+
+        self.rows.drain(index + 1..end);
+        self.rows[index].expanded = false;
+    }
+
+    /// The row of the parent of the entry at row `index`, or `index` for the root.
+    fn parent(&self, index: usize) -> usize {
+        let depth = self.rows[index].depth;
+        self.rows[..index].iter().rposition(|row| row.depth < depth).unwrap_or(index)
+    }
+
+    /// Read the tree again, e.g. after the filters changed, keeping the same directories
+    /// expanded and the selection on the same entry or its closest remaining ancestor.
+    fn reload(&mut self) -> Result<(), Box<Error>> {
+        let expanded: Vec<_> = self.rows
+            .iter()
+            .filter(|row| row.expanded)
+            .map(|row| row.path.clone())
+            .collect();
+        let selected = self.rows.get(self.selected).map(|row| row.path.clone());
+
+        self.rows = vec![Row {
+                             path: self.root.clone(),
+                             name: self.root.display().to_string(),
+                             is_dir: true,
+                             ignored: false,
+                             depth: 0,
+                             expanded: false,
+                             unreadable: None,
+                             mount_point: false,
+                         }];
+        try!(self.expand(0));
+
+        let mut i = 1;
+
+        while i < self.rows.len() {
+            if expanded.contains(&self.rows[i].path) {
+                try!(self.expand(i));
+            }
+
+            i += 1;
+        }
+
+        self.selected = selected.and_then(|selected| {
+                selected.ancestors()
+                    .filter_map(|path| self.rows.iter().position(|row| row.path == path))
+                    .next()
+            })
+            .unwrap_or(0);
+
+        Ok(())
+    }
+
+    /// The rows for the entries of the directory at `path`. Subdirectories that can't be read are
+    /// listed as unreadable, rather than failing the whole directory.
+    fn read_dir(&self, path: &Path, depth: usize) -> Result<Vec<Row>, Box<Error>> {
+        let mut tree_iter = try!(TreeIter::new(path, (self.make_filters)(&self.toggles))
+            .map_err(|err| format!("Failed to read '{}': {}", path.display(), err)));
+        // Only the directory itself is read, as no subdirectory is within the limit unless empty
+        tree_iter.file_limit(0);
+        // Mount points are never expanded, so `path` is on the file system of the root
+        tree_iter.one_file_system(self.one_file_system);
+
+        let mut rows = Vec::new();
+
+        for result in tree_iter {
+            let event = match result {
+                Ok(event) => event,
+                Err(err) => {
+                    rows.push(Row::unreadable(&*try!(err.downcast::<ReadDirError>()), depth));
+                    continue;
+                },
+            };
+
+            match event {
+                Event::File(entry) | Event::LimitedDir(entry, _) => {
+                    rows.push(Row::from_entry(&entry, depth))
+                },
+                // An empty directory, which is immediately closed
+                Event::OpenDir(entry) => rows.push(Row::from_entry(&entry, depth)),
+                Event::CloseDir | Event::Elided(_) => {},
+            }
+        }
+
+        Ok(rows)
+    }
+
+    fn draw<W: Write>(&mut self,
+                      screen: &mut W,
+                      width: usize,
+                      height: usize)
+                      -> Result<(), Box<Error>> {
+        let list_height = height.saturating_sub(1).max(1);
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + list_height {
+            self.scroll = self.selected + 1 - list_height;
+        }
+
+        try!(write!(screen, "{}", clear::All));
+        let has_next = self.has_next_sibling();
+        let mut dir_has_next = Vec::new();
+
+        for (i, row) in self.rows.iter().enumerate() {
+            // The lines in front of a row depend on all its ancestors, even off screen
+            dir_has_next.truncate(row.depth.saturating_sub(1));
+            if row.depth > 0 {
+                dir_has_next.push(has_next[i]);
+            }
+
+            if i < self.scroll {
+                continue;
+            } else if i >= self.scroll + list_height {
+                break;
+            }
+
+            let mut label = tree_prefix(&dir_has_next);
+            label.push_str(&row.name);
+
+            if row.is_dir && row.depth > 0 {
+                label.push('/');
+            }
+
+            if row.unreadable.is_some() {
+                label.push_str(" [unreadable]");
+            }
+
+            if row.mount_point {
+                label.push_str(" [mount]");
+            }
+
+            let label = truncate(&label, width);
+            let y = (i - self.scroll + 1) as u16;
+
+            try!(write!(screen, "{}", cursor::Goto(1, y)));
+
+            if i == self.selected {
+                try!(write!(screen, "{}{}{}", style::Invert, label, style::Reset));
+            } else if row.ignored {
+                try!(write!(screen, "{}{}{}", style::Faint, label, style::Reset));
+            } else {
+                try!(write!(screen, "{}", label));
+            }
+        }
+
+        let status = match (&self.search, &self.message) {
+            (&Some(ref search), _) => format!("/{}", search.query),
+            (_, &Some(ref message)) => message.clone(),
+            _ => {
+                let path = self.rows.get(self.selected).map(|row| row.path.display().to_string());
+                format!("{}  [hidden files {}] [gitignore {}]  {}",
+                        path.unwrap_or_default(),
+                        if self.toggles.show_hidden { "shown" } else { "hidden" },
+                        if self.toggles.gitignore { "on" } else { "off" },
+                        KEYS_HELP)
+            },
+        };
+
+        try!(write!(screen,
+                    "{}{}{}{}",
+                    cursor::Goto(1, height as u16),
+                    style::Bold,
+                    truncate(&status, width),
+                    style::Reset));
+        try!(screen.flush());
+
+        Ok(())
+    }
+
+    /// Whether each row has a next sibling.
+    fn has_next_sibling(&self) -> Vec<bool> {
+        let mut has_next = vec![false; self.rows.len()];
+        // Whether a row at each depth follows, without a shallower row in between
+        let mut seen: Vec<bool> = Vec::new();
+
+        for (i, row) in self.rows.iter().enumerate().rev() {
+            seen.resize(row.depth + 1, false);
+            has_next[i] = seen[row.depth];
+            seen[row.depth] = true;
+        }
+
+        has_next
+    }
+}
+
+/// The first `width` characters of `text`.
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// The size of the terminal open as `fd` in columns and rows, defaulting to 80x24.
+///
+/// Unlike `termion::terminal_size()`, this works when stdout isn't the terminal.
+fn terminal_size(fd: RawFd) -> (u16, u16) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == -1 || size.ws_row == 0 {
+        (80, 24)
+    } else {
+        (size.ws_col, size.ws_row)
+    }
+}
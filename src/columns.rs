@@ -0,0 +1,275 @@
+//! Metadata columns for long listings, in the spirit of `ls -l`.
+//!
+//! The columns are rendered as a bracketed prefix in front of each entry's name, e.g.
+//! `[drwxr-xr-x jacob    staff    Oct 19 12:00]  src`.
+
+extern crate libc;
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use super::metadata::Metadata;
+
+/// The default `strftime` format used for the date column.
+pub const DEFAULT_TIME_FORMAT: &'static str = "%b %e %H:%M";
+
+/// A selection of metadata columns along with the state needed to render them.
+#[derive(Clone, Default)]
+pub struct Columns {
+    inode: bool,
+    device: bool,
+    permissions: bool,
+    owner: bool,
+    group: bool,
+    time_format: Option<String>,
+    names: NameCache,
+}
+
+impl Columns {
+    /// Show the inode number.
+    pub fn inode(&mut self, show: bool) -> &mut Self {
+        self.inode = show;
+        self
+    }
+
+    /// Show the device id.
+    pub fn device(&mut self, show: bool) -> &mut Self {
+        self.device = show;
+        self
+    }
+
+    /// Show the file type and permissions as a mode string, e.g. `drwxr-xr-x`.
+    pub fn permissions(&mut self, show: bool) -> &mut Self {
+        self.permissions = show;
+        self
+    }
+
+    /// Show the name of the owning user.
+    pub fn owner(&mut self, show: bool) -> &mut Self {
+        self.owner = show;
+        self
+    }
+
+    /// Show the name of the owning group.
+    pub fn group(&mut self, show: bool) -> &mut Self {
+        self.group = show;
+        self
+    }
+
+    /// Show the modification time, formatted with a `strftime` style format string.
+    pub fn date(&mut self, format: String) -> &mut Self {
+        self.time_format = Some(format);
+        self
+    }
+
+    /// Whether no columns are selected.
+    pub fn is_empty(&self) -> bool {
+        !(self.inode || self.device || self.permissions || self.owner || self.group ||
+          self.time_format.is_some())
+    }
+
+    /// Render the selected columns for `metadata`.
+    ///
+    /// Returns an empty string if no columns are selected, otherwise the bracketed columns
+    /// followed by two spaces of padding.
+    pub fn format(&mut self, metadata: &Metadata) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+
+        let mut fields = Vec::new();
+
+        if self.inode {
+            fields.push(format!("{:>8}", metadata.ino()));
+        }
+
+        if self.device {
+            fields.push(format!("{:>4}", metadata.dev()));
+        }
+
+        if self.permissions {
+            fields.push(mode_string(metadata));
+        }
+
+        if self.owner {
+            fields.push(format!("{:<8}", self.names.user(metadata.uid())));
+        }
+
+        if self.group {
+            fields.push(format!("{:<8}", self.names.group(metadata.gid())));
+        }
+
+        if let Some(ref time_format) = self.time_format {
+            fields.push(format_time(metadata.mtime(), time_format));
+        }
+
+        format!("[{}]  ", fields.join(" "))
+    }
+}
+
+/// Render the file type and permission bits as in `ls -l`, e.g. `-rwsr-xr-x`.
+pub fn mode_string(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+    let mode = metadata.mode();
+
+    let type_char = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_char_device() {
+        'c'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else {
+        '-'
+    };
+
+    let mut string = String::with_capacity(10);
+    string.push(type_char);
+
+    // (read, write, execute, special bit, special char when executable)
+    let triples = [(0o400, 0o200, 0o100, 0o4000, 's'),
+                   (0o040, 0o020, 0o010, 0o2000, 's'),
+                   (0o004, 0o002, 0o001, 0o1000, 't')];
+
+    for &(read, write, execute, special, special_char) in &triples {
+        string.push(if mode & read != 0 { 'r' } else { '-' });
+        string.push(if mode & write != 0 { 'w' } else { '-' });
+        string.push(match (mode & execute != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    string
+}
+
+/// The `ls -F` style indicator for the file type of `metadata`, if any.
+///
+/// Directories get `/`, executables `*`, symlinks `@`, FIFOs `|` and sockets `=`.
+pub fn indicator(metadata: &Metadata) -> Option<char> {
+    let file_type = metadata.file_type();
+
+    if file_type.is_dir() {
+        Some('/')
+    } else if file_type.is_symlink() {
+        Some('@')
+    } else if file_type.is_fifo() {
+        Some('|')
+    } else if file_type.is_socket() {
+        Some('=')
+    } else if file_type.is_file() && metadata.mode() & 0o111 != 0 {
+        Some('*')
+    } else {
+        None
+    }
+}
+
+/// Format a Unix timestamp in local time using a `strftime` style format string.
+pub fn format_time(time: i64, format: &str) -> String {
+    let format = match CString::new(format) {
+        Ok(format) => format,
+        Err(_) => return String::new(),
+    };
+
+    let mut buf = vec![0u8; 256];
+
+    unsafe {
+        let time = time as libc::time_t;
+        let mut tm: libc::tm = mem::zeroed();
+
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+
+        let len = libc::strftime(buf.as_mut_ptr() as *mut libc::c_char,
+                                 buf.len(),
+                                 format.as_ptr(),
+                                 &tm);
+        buf.truncate(len);
+    }
+
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Caches lookups in the passwd and group databases, which may be slow (e.g. when backed by
+/// LDAP) and are repeated for nearly every entry.
+#[derive(Clone, Default)]
+pub struct NameCache {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl NameCache {
+    /// The user name of `uid`, or the numeric id if it has no name.
+    pub fn user(&mut self, uid: u32) -> &str {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| lookup_user(uid).unwrap_or_else(|| uid.to_string()))
+    }
+
+    /// The group name of `gid`, or the numeric id if it has no name.
+    pub fn group(&mut self, gid: u32) -> &str {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| lookup_group(gid).unwrap_or_else(|| gid.to_string()))
+    }
+}
+
+/// Repeatedly call a reentrant `get*_r` style function, growing the buffer while it reports
+/// `ERANGE`.
+fn with_growing_buffer<F>(mut call: F) -> Option<String>
+    where F: FnMut(&mut [libc::c_char]) -> Result<Option<String>, libc::c_int>
+{
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        match call(&mut buf) {
+            Ok(name) => return name,
+            Err(libc::ERANGE) if buf.len() < 1 << 20 => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            },
+            Err(_) => return None,
+        }
+    }
+}
+
+fn lookup_user(uid: u32) -> Option<String> {
+    with_growing_buffer(|buf| unsafe {
+        let mut passwd: libc::passwd = mem::zeroed();
+        let mut result = ptr::null_mut();
+        let code = libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result);
+
+        if code != 0 {
+            Err(code)
+        } else if result.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(CStr::from_ptr(passwd.pw_name).to_string_lossy().into_owned()))
+        }
+    })
+}
+
+fn lookup_group(gid: u32) -> Option<String> {
+    with_growing_buffer(|buf| unsafe {
+        let mut group: libc::group = mem::zeroed();
+        let mut result = ptr::null_mut();
+        let code = libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result);
+
+        if code != 0 {
+            Err(code)
+        } else if result.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(CStr::from_ptr(group.gr_name).to_string_lossy().into_owned()))
+        }
+    })
+}
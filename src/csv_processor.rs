@@ -0,0 +1,294 @@
+//! `CsvProcessor` and supporting types.
+//!
+//! This processor prints one row per entry with a selectable set of fields, as either CSV or
+//! TSV, for import into spreadsheets and other tabular tools.
+
+use super::tree_processor::TreeProcessor;
+use super::tree::Entry;
+use super::columns::{self, NameCache};
+use super::git_status::GitStatus;
+use super::hash::{self, Algorithm, MerkleStack};
+
+/// The `strftime` format of the `mtime` field, ISO 8601 with time zone offset.
+const TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// A column in the tabular output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// Path to the entry, prefixed with the root.
+    Path,
+    /// File type, e.g. `file` or `directory`.
+    Type,
+    /// Size in bytes.
+    Size,
+    /// Modification time in ISO 8601 format.
+    Mtime,
+    /// Permission bits in octal.
+    Mode,
+    /// Name of the owning user.
+    Owner,
+    /// Depth in the tree, where the root's children are at depth 1.
+    Depth,
+    /// Two letter git status code, see `GitStatus::status()`. Empty outside of repositories and
+    /// for directories.
+    GitStatus,
+    /// Hex encoded digest, see `TreeIter::hash()`. Directories have one with
+    /// `CsvProcessorBuilder::digests()`.
+    Digest,
+}
+
+/// All fields.
+pub const ALL_FIELDS: [Field; 9] = [Field::Path,
+                                    Field::Type,
+                                    Field::Size,
+                                    Field::Mtime,
+                                    Field::Mode,
+                                    Field::Owner,
+                                    Field::Depth,
+                                    Field::GitStatus,
+                                    Field::Digest];
+
+/// The fields output by default, in order.
+pub const DEFAULT_FIELDS: [Field; 8] = [Field::Path,
+                                        Field::Type,
+                                        Field::Size,
+                                        Field::Mtime,
+                                        Field::Mode,
+                                        Field::Owner,
+                                        Field::Depth,
+                                        Field::GitStatus];
+
+impl Field {
+    /// The field's name, as used in the header row.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Field::Path => "path",
+            Field::Type => "type",
+            Field::Size => "size",
+            Field::Mtime => "mtime",
+            Field::Mode => "mode",
+            Field::Owner => "owner",
+            Field::Depth => "depth",
+            Field::GitStatus => "git_status",
+            Field::Digest => "digest",
+        }
+    }
+
+    /// Look up a field by its name.
+    pub fn from_name(name: &str) -> Option<Field> {
+        ALL_FIELDS.iter().cloned().find(|field| field.name() == name)
+    }
+}
+
+/// The flavor of tabular output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Comma separated values as in RFC 4180, quoting fields when needed.
+    Csv,
+    /// Tab separated values, with tabs, newlines and backslashes escaped.
+    Tsv,
+}
+
+/// Builder for `CsvProcessor`.
+pub struct CsvProcessorBuilder {
+    format: Format,
+    fields: Vec<Field>,
+    git_status: Option<GitStatus>,
+    digests: Option<Algorithm>,
+}
+
+impl CsvProcessorBuilder {
+    /// Create a new builder including the default fields.
+    pub fn new(format: Format) -> Self {
+        CsvProcessorBuilder {
+            format: format,
+            fields: DEFAULT_FIELDS.to_vec(),
+            git_status: None,
+            digests: None,
+        }
+    }
+
+    /// Select the fields to output, in order.
+    pub fn fields(&mut self, fields: Vec<Field>) -> &mut Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Set the lookup used for the `git_status` field.
+    pub fn git_status(&mut self, git_status: GitStatus) -> &mut Self {
+        self.git_status = Some(git_status);
+        self
+    }
+
+    /// Fill in the `digest` field of directories with a Merkle-style digest of their contents,
+    /// using `algorithm`. See `hash::MerkleStack`.
+    ///
+    /// Since a directory's digest depends on its contents, directory rows are then printed after
+    /// their contents rather than before.
+    pub fn digests(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.digests = Some(algorithm);
+        self
+    }
+
+    /// Build a `CsvProcessor`.
+    ///
+    /// This method consumes the builder, and prints the header row.
+    pub fn build(self) -> CsvProcessor {
+        let processor = CsvProcessor {
+            format: self.format,
+            fields: self.fields,
+            git_status: self.git_status,
+            names: NameCache::default(),
+            depth: 0,
+            merkle: self.digests.map(MerkleStack::new),
+            pending_dirs: Vec::new(),
+        };
+
+        let header: Vec<_> = processor.fields.iter().map(|field| field.name()).collect();
+        processor.print_row(&header);
+        processor
+    }
+}
+
+/// A `TreeProcessor` printing a table with a row per entry.
+pub struct CsvProcessor {
+    format: Format,
+    fields: Vec<Field>,
+    git_status: Option<GitStatus>,
+    names: NameCache,
+    depth: usize,
+    merkle: Option<MerkleStack>,
+    /// Rows of the open directories, held back until their digests are known. The root has no
+    /// row.
+    pending_dirs: Vec<Vec<String>>,
+}
+
+impl CsvProcessor {
+    fn print_row<S: AsRef<str>>(&self, values: &[S]) {
+        let values: Vec<_> = values.iter()
+            .map(|value| match self.format {
+                Format::Csv => quote_csv(value.as_ref()),
+                Format::Tsv => escape_tsv(value.as_ref()),
+            })
+            .collect();
+
+        let separator = match self.format {
+            Format::Csv => ",",
+            Format::Tsv => "\t",
+        };
+
+        println!("{}", values.join(separator));
+    }
+
+    fn print_entry(&mut self, entry: &Entry) {
+        let values = self.values(entry);
+        self.print_row(&values);
+    }
+
+    /// The values of the fields for `entry`. The digest of a directory is left empty.
+    fn values(&mut self, entry: &Entry) -> Vec<String> {
+        let metadata = entry.metadata();
+        let mut values = Vec::with_capacity(self.fields.len());
+
+        for field in &self.fields {
+            values.push(match *field {
+                Field::Path => {
+                    let path = entry.path();
+                    path.strip_prefix(".").unwrap_or(path).to_string_lossy().into_owned()
+                },
+                Field::Type => String::from(metadata.file_type().name()),
+                Field::Size => metadata.len().to_string(),
+                Field::Mtime => columns::format_time(metadata.mtime(), TIME_FORMAT),
+                Field::Mode => format!("{:04o}", metadata.mode()),
+                Field::Owner => String::from(self.names.user(metadata.uid())),
+                Field::Depth => self.depth.to_string(),
+                Field::GitStatus => {
+                    match self.git_status {
+                        // Git doesn't track directories
+                        Some(ref git_status) if !metadata.is_dir() => {
+                            git_status.status(entry.path()).unwrap_or_default()
+                        },
+                        _ => String::new(),
+                    }
+                },
+                Field::Digest => entry.digest().map(hash::to_hex).unwrap_or_default(),
+            });
+        }
+
+        values
+    }
+
+    /// Whether directory rows are held back until their digests are known.
+    fn defers_dirs(&self) -> bool {
+        self.merkle.is_some() && self.fields.contains(&Field::Digest)
+    }
+}
+
+impl TreeProcessor for CsvProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        self.depth += 1;
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.open_dir(entry);
+        }
+
+        if self.defers_dirs() {
+            let values = self.values(entry);
+            self.pending_dirs.push(values);
+        } else {
+            self.print_entry(entry);
+        }
+    }
+
+    fn close_dir(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+
+        let digest = match self.merkle {
+            Some(ref mut merkle) => merkle.close_dir(),
+            None => return,
+        };
+
+        if self.defers_dirs() {
+            if let Some(mut values) = self.pending_dirs.pop() {
+                for (value, field) in values.iter_mut().zip(&self.fields) {
+                    if *field == Field::Digest {
+                        *value = hash::to_hex(&digest);
+                    }
+                }
+
+                self.print_row(&values);
+            }
+        }
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        self.depth += 1;
+        self.print_entry(entry);
+        self.depth -= 1;
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, _num_entries: usize) {
+        self.file(entry);
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn quote_csv(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+/// Escape the characters that would break the structure of a TSV file.
+fn escape_tsv(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
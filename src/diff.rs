@@ -0,0 +1,443 @@
+//! Comparing two trees.
+//!
+//! Each tree is collected from an `EventSource` into a `Node`, with the entries of every
+//! directory sorted by name. `diff()` then walks both trees in merged order, and `DiffPrinter`
+//! renders the result as a single tree.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use super::hash::{self, Algorithm};
+use super::metadata::{FileType, Metadata};
+use super::print_processor::tree_prefix;
+use super::source::{FsSource, TreeSource};
+use super::tree::{Entry, Event, EventSource};
+
+/// An entry of a collected tree.
+pub struct Node {
+    path: PathBuf,
+    metadata: Metadata,
+    /// Entries by name, for directories that were descended into.
+    children: Option<BTreeMap<OsString, Node>>,
+    /// A known digest of the contents, see `set_digest()`.
+    digest: Option<Vec<u8>>,
+    /// Where to read the contents from when the digest isn't known, if anywhere.
+    source: Option<Rc<TreeSource>>,
+}
+
+impl Node {
+    /// Create a node for an entry at `path` on disk, e.g. when loading a saved tree. Directories
+    /// start out empty.
+    pub fn new(path: PathBuf, metadata: Metadata) -> Node {
+        Node {
+            children: if metadata.is_dir() { Some(BTreeMap::new()) } else { None },
+            path: path,
+            metadata: metadata,
+            digest: None,
+            source: Some(Rc::new(FsSource)),
+        }
+    }
+
+    /// Collect the events of `events` into a tree, rooted at `root`.
+    ///
+    /// Elided entries are left out, and directories that weren't descended into are compared
+    /// like files. Contents are read through the source of `events`, unless it has none.
+    pub fn collect(events: &mut EventSource, root: PathBuf) -> Result<Node, Box<Error>> {
+        let source = events.source();
+        let mut stack = vec![Node::new(root, Metadata::new(FileType::Dir))];
+
+        while let Some(result) = events.next() {
+            match try!(result) {
+                Event::OpenDir(entry) => {
+                    let mut node = Node::from_entry(&entry, &source);
+                    node.children = Some(BTreeMap::new());
+                    stack.push(node);
+                },
+                Event::CloseDir => {
+                    let node = try!(stack.pop().ok_or("Unbalanced directory events"));
+
+                    match stack.last_mut() {
+                        Some(parent) => parent.insert(node),
+                        None => return Ok(node),
+                    }
+                },
+                Event::File(entry) | Event::LimitedDir(entry, _) => {
+                    let node = Node::from_entry(&entry, &source);
+
+                    if let Some(parent) = stack.last_mut() {
+                        parent.insert(node);
+                    }
+                },
+                Event::Elided(_) => {},
+            }
+        }
+
+        Err(From::from("The tree ended before its root was closed"))
+    }
+
+    fn from_entry(entry: &Entry, source: &Rc<TreeSource>) -> Node {
+        // Members of an expanded archive aren't in the source of the tree holding it
+        let readable = source.has_contents() && !entry.is_archive_member();
+
+        Node {
+            path: entry.path().to_path_buf(),
+            metadata: entry.metadata().clone(),
+            children: None,
+            digest: None,
+            source: if readable { Some(source.clone()) } else { None },
+        }
+    }
+
+    /// Add `node` to the entries of this directory, replacing any entry with the same name.
+    pub fn insert(&mut self, node: Node) {
+        let name = node.path.file_name().map(OsString::from).unwrap_or_default();
+
+        if let Some(ref mut children) = self.children {
+            children.insert(name, node);
+        }
+    }
+
+    /// Add `node` to the entries of the directory at `parent`, relative to this directory.
+    pub fn insert_at(&mut self, parent: &Path, node: Node) -> Result<(), Box<Error>> {
+        let mut dir = self;
+
+        for component in parent.components() {
+            let child = dir.children
+                .as_mut()
+                .and_then(|children| children.get_mut(component.as_os_str()));
+
+            dir = match child {
+                Some(child) => child,
+                None => {
+                    return Err(From::from(format!("No directory '{}' for '{}'",
+                                                  parent.display(),
+                                                  node.path.display())))
+                },
+            };
+        }
+
+        dir.insert(node);
+        Ok(())
+    }
+
+    /// Use `digest` when comparing contents, rather than hashing the contents at `path()`. It must
+    /// have been computed with the algorithm passed to `diff()`.
+    pub fn set_digest(&mut self, digest: Vec<u8>) {
+        self.digest = Some(digest);
+    }
+
+    /// Path of the entry, prefixed with the root.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// How an entry differs between two trees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Unchanged,
+    /// Only in the new tree.
+    Added,
+    /// Only in the old tree.
+    Removed,
+    /// In both trees, but different. For directories, this means some entry within differs.
+    Changed,
+}
+
+/// An entry in the difference between two trees.
+#[derive(Debug)]
+pub struct DiffEntry {
+    pub name: OsString,
+    pub change: Change,
+    pub is_dir: bool,
+    /// The differences within a directory, sorted by name.
+    pub children: Vec<DiffEntry>,
+}
+
+impl DiffEntry {
+    /// An entry and everything within it, all marked with `change`.
+    fn whole(name: &OsString, node: &Node, change: Change) -> DiffEntry {
+        DiffEntry {
+            name: name.clone(),
+            change: change,
+            is_dir: node.metadata.is_dir(),
+            children: node.children
+                .iter()
+                .flat_map(|children| children.iter())
+                .map(|(name, child)| DiffEntry::whole(name, child, change))
+                .collect(),
+        }
+    }
+}
+
+/// Compare the contents of two directories.
+///
+/// Files are changed if their type, permissions, size or mtime differ, or when comparing content,
+/// if their type, permissions, size or content digest with the given algorithm differ. Files
+/// whose contents can't be read on either side are compared by mtime.
+pub fn diff(old: &Node,
+            new: &Node,
+            compare_content: Option<Algorithm>)
+            -> Result<Vec<DiffEntry>, Box<Error>> {
+    let empty = BTreeMap::new();
+    let old_children = old.children.as_ref().unwrap_or(&empty);
+    let new_children = new.children.as_ref().unwrap_or(&empty);
+
+    let mut old_iter = old_children.iter().peekable();
+    let mut new_iter = new_children.iter().peekable();
+    let mut entries = Vec::new();
+
+    loop {
+        let order = match (old_iter.peek(), new_iter.peek()) {
+            (Some(&(old_name, _)), Some(&(new_name, _))) => old_name.cmp(new_name),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match order {
+            Ordering::Less => {
+                let (name, old) = old_iter.next().unwrap();
+                entries.push(DiffEntry::whole(name, old, Change::Removed));
+            },
+            Ordering::Greater => {
+                let (name, new) = new_iter.next().unwrap();
+                entries.push(DiffEntry::whole(name, new, Change::Added));
+            },
+            Ordering::Equal => {
+                let (name, old) = old_iter.next().unwrap();
+                let (_, new) = new_iter.next().unwrap();
+
+                if old.metadata.file_type() != new.metadata.file_type() {
+                    entries.push(DiffEntry::whole(name, old, Change::Removed));
+                    entries.push(DiffEntry::whole(name, new, Change::Added));
+                } else if old.children.is_some() && new.children.is_some() {
+                    let children = try!(diff(old, new, compare_content));
+                    let change = if children.iter().all(|child| child.change == Change::Unchanged) {
+                        Change::Unchanged
+                    } else {
+                        Change::Changed
+                    };
+
+                    entries.push(DiffEntry {
+                        name: name.clone(),
+                        change: change,
+                        is_dir: true,
+                        children: children,
+                    });
+                } else {
+                    let change = if try!(differs(old, new, compare_content)) {
+                        Change::Changed
+                    } else {
+                        Change::Unchanged
+                    };
+
+                    entries.push(DiffEntry {
+                        name: name.clone(),
+                        change: change,
+                        is_dir: old.metadata.is_dir(),
+                        children: Vec::new(),
+                    });
+                }
+            },
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Whether two entries differ.
+fn differs(old: &Node, new: &Node, compare_content: Option<Algorithm>) -> Result<bool, Box<Error>> {
+    if old.metadata.file_type() != new.metadata.file_type() ||
+       old.metadata.mode() != new.metadata.mode() ||
+       old.metadata.len() != new.metadata.len() {
+        return Ok(true);
+    }
+
+    // Only regular files have contents worth hashing
+    if let Some(algorithm) = compare_content {
+        if old.metadata.is_file() {
+            if let (Some(old), Some(new)) = (try!(digest(old, algorithm)),
+                                             try!(digest(new, algorithm))) {
+                return Ok(old != new);
+            }
+        }
+    }
+
+    Ok(old.metadata.mtime() != new.metadata.mtime())
+}
+
+/// The digest of the contents of `node`, hashing them unless it's known. `None` if neither the
+/// digest nor the contents are available.
+fn digest(node: &Node, algorithm: Algorithm) -> Result<Option<Vec<u8>>, Box<Error>> {
+    match (&node.digest, &node.source) {
+        (&Some(ref digest), _) => Ok(Some(digest.clone())),
+        (&None, &Some(ref source)) => {
+            let file = try!(source.open(&node.path));
+            hash::reader_digest(file, algorithm).map(Some).map_err(From::from)
+        },
+        (&None, &None) => Ok(None),
+    }
+}
+
+/// Number of entries of each kind of change.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+/// Prints the difference between two trees as a single tree.
+///
+/// Added entries are marked with `+`, removed with `-` and changed with `~`. Directories with
+/// changes somewhere within are listed without a marker, and by default unchanged entries are
+/// collapsed into a count per directory.
+///
+/// # Example
+///
+/// ```text
+/// build-1 -> build-2
+/// ├── + new.txt
+/// ├── lib
+/// │   ├── ~ liboak.so
+/// │   └── … 3 unchanged
+/// └── - old.txt
+///
+/// 1 added, 1 removed, 1 changed
+/// ```
+pub struct DiffPrinter {
+    colored: bool,
+    collapse_unchanged: bool,
+}
+
+impl DiffPrinter {
+    /// Create a new printer.
+    pub fn new() -> Self {
+        DiffPrinter {
+            colored: false,
+            collapse_unchanged: true,
+        }
+    }
+
+    /// Color the markers using ANSI escape codes.
+    pub fn colored(&mut self, colored: bool) -> &mut Self {
+        self.colored = colored;
+        self
+    }
+
+    /// Collapse unchanged entries into a count, which is the default. Otherwise every entry is
+    /// listed, so the output is the new tree annotated with the changes.
+    pub fn collapse_unchanged(&mut self, collapse: bool) -> &mut Self {
+        self.collapse_unchanged = collapse;
+        self
+    }
+
+    /// Print `entries`, the difference between the trees rooted at `old` and `new`.
+    pub fn print(&self, old: &Path, new: &Path, entries: &[DiffEntry]) -> DiffSummary {
+        let mut summary = DiffSummary::default();
+
+        println!("{} -> {}", old.display(), new.display());
+        self.print_entries(entries, &mut Vec::new(), &mut summary);
+
+        println!("\n{} added, {} removed, {} changed",
+                 summary.added,
+                 summary.removed,
+                 summary.changed);
+
+        summary
+    }
+
+    fn print_entries(&self,
+                     entries: &[DiffEntry],
+                     dir_has_next: &mut Vec<bool>,
+                     summary: &mut DiffSummary) {
+        let shown: Vec<_> = entries.iter()
+            .filter(|entry| !self.collapse_unchanged || entry.change != Change::Unchanged)
+            .collect();
+        let num_unchanged = entries.len() - shown.len();
+
+        for (i, entry) in shown.iter().enumerate() {
+            dir_has_next.push(i + 1 < shown.len() || num_unchanged > 0);
+            println!("{}{}", tree_prefix(dir_has_next), self.label(entry));
+
+            match entry.change {
+                Change::Added => summary.added += 1,
+                Change::Removed => summary.removed += 1,
+                Change::Changed if !entry.is_dir => summary.changed += 1,
+                _ => {},
+            }
+
+            self.print_entries(&entry.children, dir_has_next, summary);
+            dir_has_next.pop();
+        }
+
+        if num_unchanged > 0 {
+            dir_has_next.push(false);
+            println!("{}… {} unchanged", tree_prefix(dir_has_next), num_unchanged);
+            dir_has_next.pop();
+        }
+    }
+
+    fn label(&self, entry: &DiffEntry) -> String {
+        let name = entry.name.to_string_lossy();
+        let (marker, color) = match entry.change {
+            Change::Added => ("+", "32"),
+            Change::Removed => ("-", "31"),
+            Change::Changed if !entry.is_dir => ("~", "33"),
+            _ => return name.into_owned(),
+        };
+
+        if self.colored {
+            format!("\x1b[{}m{} {}\x1b[0m", color, marker, name)
+        } else {
+            format!("{} {}", marker, name)
+        }
+    }
+}
+
+impl Default for DiffPrinter {
+    fn default() -> Self {
+        DiffPrinter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+    use super::super::metadata::{FileType, Metadata};
+
+    /// A tree holding a single file `main.c` with `mode`.
+    fn tree(root: &str, mode: u32) -> Node {
+        let mut metadata = Metadata::new(FileType::File);
+        metadata.len = 12;
+        metadata.mode = mode;
+        metadata.mtime = 1500000000;
+
+        let mut tree = Node::new(PathBuf::from(root), Metadata::new(FileType::Dir));
+        tree.insert(Node::new(PathBuf::from(root).join("main.c"), metadata));
+        tree
+    }
+
+    fn changes(old: &Node, new: &Node) -> Vec<Change> {
+        diff(old, new, None).unwrap().iter().map(|entry| entry.change).collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(changes(&tree("old", 0o644), &tree("new", 0o644)), [Change::Unchanged]);
+    }
+
+    #[test]
+    fn permissions_only() {
+        assert_eq!(changes(&tree("old", 0o644), &tree("new", 0o755)), [Change::Changed]);
+    }
+}
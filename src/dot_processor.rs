@@ -0,0 +1,139 @@
+//! `DotProcessor` and supporting types.
+//!
+//! This processor outputs the tree as a Graphviz `digraph`, suitable for rendering with e.g.
+//! `dot -Tsvg`.
+
+use std::path::PathBuf;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+
+/// Font sizes of file nodes when weighted by size.
+const MIN_FONT_SIZE: f64 = 10.0;
+const MAX_FONT_SIZE: f64 = 36.0;
+
+/// Builder for `DotProcessor`.
+pub struct DotProcessorBuilder {
+    root: PathBuf,
+    size_weighted: bool,
+}
+
+impl DotProcessorBuilder {
+    /// Create a new builder.
+    pub fn new(root: PathBuf) -> Self {
+        DotProcessorBuilder {
+            root: root,
+            size_weighted: false,
+        }
+    }
+
+    /// Scale the font size of file nodes logarithmically by file size.
+    pub fn size_weighted(&mut self, size_weighted: bool) -> &mut Self {
+        self.size_weighted = size_weighted;
+        self
+    }
+
+    /// Build a `DotProcessor`.
+    ///
+    /// This method also prints the graph header and the root node.
+    pub fn build(&self) -> DotProcessor {
+        println!("digraph tree {{");
+        println!("  rankdir=LR;");
+        println!("  node [fontname=\"sans-serif\", fontsize={}];", MIN_FONT_SIZE);
+        println!("  n0 [label={}, shape=folder];", quote(&self.root.to_string_lossy()));
+
+        DotProcessor {
+            size_weighted: self.size_weighted,
+            parents: vec![0],
+            next_id: 1,
+        }
+    }
+}
+
+/// A `TreeProcessor` printing the tree as a Graphviz graph.
+///
+/// Each entry is a node, with an edge from its parent directory. Directories are drawn as
+/// folders and files as notes, while symlinks get a dashed outline.
+pub struct DotProcessor {
+    size_weighted: bool,
+    /// Node ids of the currently open directories, innermost last.
+    parents: Vec<usize>,
+    next_id: usize,
+}
+
+impl DotProcessor {
+    /// Print a node with an edge from the current directory, returning its id.
+    fn print_node(&mut self, label: &str, attributes: &str) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        println!("  n{} [label={}{}];", id, quote(label), attributes);
+
+        if let Some(parent) = self.parents.last() {
+            println!("  n{} -> n{};", parent, id);
+        }
+
+        id
+    }
+
+    fn font_size(&self, size: u64) -> f64 {
+        let scaled = MIN_FONT_SIZE + 2.0 * (1.0 + size as f64 / 1024.0).log2();
+        scaled.min(MAX_FONT_SIZE)
+    }
+}
+
+impl TreeProcessor for DotProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        let id = self.print_node(&entry.file_name(), ", shape=folder");
+        self.parents.push(id);
+    }
+
+    fn close_dir(&mut self) {
+        self.parents.pop();
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        let metadata = entry.metadata();
+        let mut attributes = String::from(", shape=note");
+
+        if metadata.file_type().is_symlink() {
+            attributes.push_str(", style=dashed");
+        }
+
+        if self.size_weighted {
+            attributes.push_str(&format!(", fontsize={:.1}", self.font_size(metadata.len())));
+        }
+
+        self.print_node(&entry.file_name(), &attributes);
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        let label = format!("{}\n({} entries)", entry.file_name(), num_entries);
+        self.print_node(&label, ", shape=folder, style=dashed");
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        self.print_node(&format!("… {} more", num_entries), ", shape=plaintext");
+    }
+
+    fn finish(&mut self, _tree: &EventSource) {
+        println!("}}");
+    }
+}
+
+/// Quote `text` as a DOT string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,331 @@
+//! `DuplicatesProcessor` and supporting types.
+//!
+//! This processor finds files with identical contents among the entries of a tree. Files are
+//! narrowed down in stages, each only looking at the candidates left by the previous one: first
+//! by size, then by a hash of their first few kilobytes, and last by a hash of their full
+//! contents. Most files thus never have to be read in full.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Write, stderr};
+use std::path::PathBuf;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+use super::hash::{self, Algorithm, Hasher};
+use super::source::TreeSource;
+
+/// Number of bytes hashed from the start of each file in the partial stage.
+pub const PARTIAL_SIZE: usize = 4 * 1024;
+
+/// Builder for `DuplicatesProcessor`.
+pub struct DuplicatesProcessorBuilder {
+    algorithm: Algorithm,
+}
+
+impl DuplicatesProcessorBuilder {
+    /// Create a new builder.
+    pub fn new() -> Self {
+        DuplicatesProcessorBuilder { algorithm: Algorithm::Sha256 }
+    }
+
+    /// Set the algorithm used to hash contents, which defaults to `Algorithm::Sha256`.
+    ///
+    /// Digests already attached to entries, see `TreeIter::hash()`, are used instead of hashing
+    /// again, so they must have been computed with the same algorithm.
+    pub fn algorithm(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Build a `DuplicatesProcessor`.
+    pub fn build(&self) -> DuplicatesProcessor {
+        DuplicatesProcessor {
+            algorithm: self.algorithm,
+            files: Vec::new(),
+            inodes: HashSet::new(),
+            num_unreadable: 0,
+        }
+    }
+}
+
+impl Default for DuplicatesProcessorBuilder {
+    fn default() -> Self {
+        DuplicatesProcessorBuilder::new()
+    }
+}
+
+/// A group of files with identical contents.
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    /// Size of each file in bytes.
+    pub size: u64,
+    /// Paths to the files, sorted.
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Bytes that could be saved by keeping only one of the files.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// A file collected during the walk.
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    digest: Option<Vec<u8>>,
+    archive_member: bool,
+}
+
+/// A `TreeProcessor` printing groups of duplicate files once the walk is done.
+///
+/// Only regular files are considered, and empty files are left out since they're all trivially
+/// identical. Hard links to the same file are counted once, as they waste no space. Files that
+/// can't be read are reported on stderr and skipped, as are files whose contents aren't available
+/// at all, like archive members or the entries of a saved listing without digests, which are
+/// counted on stderr instead. Groups are printed in order of wasted bytes, largest first.
+///
+/// # Example
+///
+/// ```text
+/// 3 files of 1048576 bytes each, 2097152 bytes wasted
+///     build-1/liboak.so
+///     build-2/liboak.so
+///     build-3/liboak.so
+///
+/// 1 group of duplicates, 2097152 bytes wasted
+/// ```
+pub struct DuplicatesProcessor {
+    algorithm: Algorithm,
+    files: Vec<Candidate>,
+    /// Device and inode numbers of the files collected, to skip hard links.
+    inodes: HashSet<(u64, u64)>,
+    /// Number of files left out of the groups as their contents aren't available.
+    num_unreadable: usize,
+}
+
+impl DuplicatesProcessor {
+    /// Group the files collected so far by content, reading them from `source` where their
+    /// digest isn't known. Files that have no digest and can't be read from `source` are left
+    /// out.
+    pub fn groups(&mut self, source: &TreeSource) -> Vec<DuplicateGroup> {
+        let files = ::std::mem::replace(&mut self.files, Vec::new());
+        let algorithm = self.algorithm;
+
+        // Members of an expanded archive aren't in the source of the tree holding it
+        let (files, unreadable): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+            file.digest.is_some() || (source.has_contents() && !file.archive_member)
+        });
+        self.num_unreadable += unreadable.len();
+
+        let groups = split_groups(vec![files], |file| Some(file.size));
+        let groups = split_groups(groups, |file| partial_digest(source, file, algorithm));
+        let groups = split_groups(groups, |file| full_digest(source, file, algorithm));
+
+        let mut groups: Vec<_> = groups.into_iter()
+            .map(|group| {
+                let mut paths: Vec<_> = group.iter().map(|file| file.path.clone()).collect();
+                paths.sort();
+
+                DuplicateGroup {
+                    size: group[0].size,
+                    paths: paths,
+                }
+            })
+            .collect();
+
+        groups.sort_by(|a, b| b.wasted_bytes().cmp(&a.wasted_bytes()).then(a.paths.cmp(&b.paths)));
+        groups
+    }
+}
+
+impl TreeProcessor for DuplicatesProcessor {
+    fn open_dir(&mut self, _entry: &Entry) {}
+
+    fn close_dir(&mut self) {}
+
+    fn file(&mut self, entry: &Entry) {
+        let metadata = entry.metadata();
+
+        if !metadata.is_file() || metadata.len() == 0 {
+            return;
+        }
+
+        // Entries without an inode, like archive members, can't be hard links
+        if metadata.ino() != 0 && !self.inodes.insert((metadata.dev(), metadata.ino())) {
+            return;
+        }
+
+        self.files.push(Candidate {
+            path: entry.path().to_path_buf(),
+            size: metadata.len(),
+            digest: entry.digest().map(|digest| digest.to_vec()),
+            archive_member: entry.is_archive_member(),
+        });
+    }
+
+    fn limited_dir(&mut self, _entry: &Entry, _num_entries: usize) {}
+
+    fn finish(&mut self, tree: &EventSource) {
+        let groups = self.groups(&*tree.source());
+        let mut wasted_bytes = 0;
+
+        if self.num_unreadable > 0 {
+            let _ = writeln!(stderr(),
+                             "Skipped {} {} whose contents can't be read",
+                             self.num_unreadable,
+                             if self.num_unreadable == 1 { "file" } else { "files" });
+        }
+
+        for group in &groups {
+            println!("{} files of {} bytes each, {} bytes wasted",
+                     group.paths.len(),
+                     group.size,
+                     group.wasted_bytes());
+
+            for path in &group.paths {
+                println!("    {}", path.strip_prefix(".").unwrap_or(path).display());
+            }
+
+            println!("");
+            wasted_bytes += group.wasted_bytes();
+        }
+
+        println!("{} {} of duplicates, {} bytes wasted",
+                 groups.len(),
+                 if groups.len() == 1 { "group" } else { "groups" },
+                 wasted_bytes);
+    }
+}
+
+/// Split each of `groups` by the key of its files, keeping only groups of two or more files.
+/// Files without a key are dropped.
+fn split_groups<K, F>(groups: Vec<Vec<Candidate>>, mut key: F) -> Vec<Vec<Candidate>>
+    where K: Ord,
+          F: FnMut(&Candidate) -> Option<K>
+{
+    let mut split = Vec::new();
+
+    for group in groups {
+        let mut by_key = BTreeMap::new();
+
+        for file in group {
+            if let Some(key) = key(&file) {
+                by_key.entry(key).or_insert_with(Vec::new).push(file);
+            }
+        }
+
+        split.extend(by_key.into_iter().map(|(_, group)| group).filter(|group| group.len() > 1));
+    }
+
+    split
+}
+
+/// The digest of the first `PARTIAL_SIZE` bytes of a file, or of all of it if the digest is
+/// already known.
+fn partial_digest(source: &TreeSource, file: &Candidate, algorithm: Algorithm) -> Option<Vec<u8>> {
+    if let Some(ref digest) = file.digest {
+        return Some(digest.clone());
+    }
+
+    let result = source.open(&file.path).and_then(|file| {
+        let mut buffer = Vec::with_capacity(PARTIAL_SIZE);
+        try!(file.take(PARTIAL_SIZE as u64).read_to_end(&mut buffer));
+
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(&buffer);
+        Ok(hasher.finish())
+    });
+
+    warn_on_error(file, result)
+}
+
+fn full_digest(source: &TreeSource, file: &Candidate, algorithm: Algorithm) -> Option<Vec<u8>> {
+    match file.digest {
+        Some(ref digest) => Some(digest.clone()),
+        // The partial digest covered the whole file
+        None if file.size <= PARTIAL_SIZE as u64 => Some(Vec::new()),
+        None => {
+            let result = source.open(&file.path)
+                .and_then(|file| hash::reader_digest(file, algorithm));
+            warn_on_error(file, result)
+        },
+    }
+}
+
+fn warn_on_error(file: &Candidate, result: io::Result<Vec<u8>>) -> Option<Vec<u8>> {
+    match result {
+        Ok(digest) => Some(digest),
+        Err(err) => {
+            let _ = writeln!(stderr(), "Failed to read '{}': {}", file.path.display(), err);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+    use super::super::metadata::{FileType, Metadata};
+    use super::super::replay::{Node, NodeInfo, NodeSource};
+    use super::super::source::MemorySource;
+    use super::super::tree::{Event, TreeIter};
+
+    fn include_all(_path: &::std::path::Path,
+                   _metadata: &Metadata)
+                   -> Result<bool, Box<::std::error::Error>> {
+        Ok(true)
+    }
+
+    fn file_metadata(len: u64) -> Metadata {
+        let mut metadata = Metadata::new(FileType::File);
+        metadata.len = len;
+        metadata
+    }
+
+    /// The paths of each group of duplicates in `tree_iter`, and the number of files left out.
+    fn groups(mut tree_iter: TreeIter) -> (Vec<Vec<PathBuf>>, usize) {
+        let mut processor = DuplicatesProcessorBuilder::new().build();
+
+        for event in tree_iter.by_ref() {
+            if let Event::File(entry) = event.unwrap() {
+                processor.file(&entry);
+            }
+        }
+
+        let groups = processor.groups(&*tree_iter.source());
+        (groups.into_iter().map(|group| group.paths).collect(), processor.num_unreadable)
+    }
+
+    #[test]
+    fn no_inode() {
+        let mut source = MemorySource::new(".");
+        source.entry("a", file_metadata(3), b"abc")
+            .entry("b", file_metadata(3), b"abc")
+            .file("c", b"abc");
+        let tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+
+        let paths = ["./a", "./b", "./c"].iter().map(PathBuf::from).collect();
+        assert_eq!(groups(tree_iter), (vec![paths], 0));
+    }
+
+    #[test]
+    fn unreadable() {
+        let node = |name: &str, digest: Option<&[u8]>| {
+            let mut info = NodeInfo::new(String::from(name), file_metadata(3));
+            info.digest = digest.map(|digest| digest.to_vec());
+            Node::File(info)
+        };
+        let source = NodeSource::new(PathBuf::from("."),
+                                     vec![node("a", Some(b"digest")),
+                                          node("b", Some(b"digest")),
+                                          node("c", None),
+                                          node("d", None)])
+            .unwrap();
+        let tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+
+        let paths = vec![PathBuf::from("./a"), PathBuf::from("./b")];
+        assert_eq!(groups(tree_iter), (vec![paths], 2));
+    }
+}
@@ -3,56 +3,252 @@
 extern crate git2;
 extern crate globset;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use self::git2::Repository;
-use self::globset::{Glob, GlobSet, GlobSetBuilder};
+use self::globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use std::result;
 use std::error::Error;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use super::metadata::Metadata;
+use super::source::{FsSource, TreeSource};
 
 type Result = result::Result<bool, Box<Error>>;
 
+/// The outcome of filtering a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The file should be included.
+    Include,
+    /// The file should be left out.
+    Exclude,
+    /// The file should be included, but marked as ignored.
+    Annotate,
+}
+
 /// A filter used to decide whether to include a file in a collection.
+///
+/// Filters are given the file's metadata along with its path, and should prefer it over querying
+/// the file system, since the file may only exist in e.g. a replayed listing.
 pub trait FileFilter {
     /// `Ok(true)` means the file should be included and vice versa.
-    fn filter(&self, path: &Path) -> Result;
+    fn filter(&self, path: &Path, metadata: &Metadata) -> Result;
+
+    /// Like `filter()`, but with the option of marking a file rather than excluding it.
+    ///
+    /// By default this is `Include` or `Exclude` depending on `filter()`.
+    fn verdict(&self, path: &Path, metadata: &Metadata) -> result::Result<Verdict, Box<Error>> {
+        self.filter(path, metadata)
+            .map(|include| if include { Verdict::Include } else { Verdict::Exclude })
+    }
+
+    /// Like `verdict()`, for a file that isn't on disk, e.g. a member of an expanded archive.
+    ///
+    /// By default this is `verdict()`. Filters that have to query the file system can't judge
+    /// such files, and should include them.
+    fn detached_verdict(&self,
+                        path: &Path,
+                        metadata: &Metadata)
+                        -> result::Result<Verdict, Box<Error>> {
+        self.verdict(path, metadata)
+    }
+
+    /// Describe why `path` is excluded by this filter, if the filter can tell.
+    ///
+    /// Only meaningful for paths where `filter()` returns `Ok(false)`.
+    fn explain(&self, _path: &Path, _metadata: &Metadata) -> Option<String> {
+        None
+    }
 }
 
 impl<F> FileFilter for F
-    where F: Fn(&Path) -> Result
+    where F: Fn(&Path, &Metadata) -> Result
 {
-    fn filter(&self, path: &Path) -> Result {
-        (self)(path)
+    fn filter(&self, path: &Path, metadata: &Metadata) -> Result {
+        (self)(path, metadata)
+    }
+}
+
+/// Entries excluded by one of the filters in a `FilterAggregate`.
+#[derive(Clone, Debug)]
+pub struct Rejections {
+    /// The name the filter was added with.
+    pub name: String,
+    /// Number of excluded paths.
+    pub count: usize,
+    /// The excluded paths, if enabled with `FilterAggregate::record_paths()`.
+    pub paths: Vec<PathBuf>,
+}
+
+/// A shared handle to the rejection statistics of a `FilterAggregate`.
+///
+/// The handle remains usable after the aggregate has been moved, e.g. into a `TreeIter`.
+#[derive(Clone, Default)]
+pub struct FilterStats {
+    rejections: Rc<RefCell<Vec<Rejections>>>,
+}
+
+impl FilterStats {
+    /// The rejections of each filter so far, in the order the filters were added.
+    pub fn rejections(&self) -> Vec<Rejections> {
+        self.rejections.borrow().clone()
+    }
+
+    /// Total number of excluded paths.
+    pub fn total(&self) -> usize {
+        self.rejections.borrow().iter().map(|rejections| rejections.count).sum()
     }
 }
 
 /// A collection of filters acting as one.
+///
+/// A path is excluded by the first filter rejecting it, which is also the filter it's counted
+/// against in the statistics. Filters added with `push_annotating()` never exclude paths, but
+/// mark them with `Verdict::Annotate` unless another filter excludes them.
 pub struct FilterAggregate {
     filters: Vec<Box<FileFilter>>,
+    annotating: Vec<bool>,
+    stats: FilterStats,
+    record_paths: bool,
 }
 
 impl FilterAggregate {
     /// Add a filter to the collection.
     pub fn push<F>(&mut self, filter: F)
         where F: FileFilter + 'static
+    {
+        let name = format!("filter {}", self.filters.len() + 1);
+        self.push_named(&name, filter);
+    }
+
+    /// Add a filter to the collection, with a name used in statistics and explanations.
+    pub fn push_named<F>(&mut self, name: &str, filter: F)
+        where F: FileFilter + 'static
+    {
+        self.push_filter(name, filter, false);
+    }
+
+    /// Add a filter whose rejected paths are marked as ignored rather than excluded.
+    pub fn push_annotating<F>(&mut self, name: &str, filter: F)
+        where F: FileFilter + 'static
+    {
+        self.push_filter(name, filter, true);
+    }
+
+    fn push_filter<F>(&mut self, name: &str, filter: F, annotating: bool)
+        where F: FileFilter + 'static
     {
         self.filters.push(Box::new(filter));
+        self.annotating.push(annotating);
+        self.stats.rejections.borrow_mut().push(Rejections {
+            name: From::from(name),
+            count: 0,
+            paths: Vec::new(),
+        });
+    }
+
+    /// Keep the excluded paths in the statistics, not only the counts.
+    pub fn record_paths(&mut self, record: bool) -> &mut Self {
+        self.record_paths = record;
+        self
+    }
+
+    /// A handle to the rejection statistics of this collection.
+    pub fn stats(&self) -> FilterStats {
+        self.stats.clone()
+    }
+
+    /// The verdict of the filters on `path`, asking each for its `detached_verdict()` if
+    /// `detached` is set.
+    fn judge(&self,
+             path: &Path,
+             metadata: &Metadata,
+             detached: bool)
+             -> result::Result<Verdict, Box<Error>> {
+        let mut verdict = Verdict::Include;
+
+        for (i, f) in self.filters.iter().enumerate() {
+            let include = if detached {
+                try!(f.detached_verdict(path, metadata)) != Verdict::Exclude
+            } else {
+                try!(f.filter(path, metadata))
+            };
+
+            if include {
+                continue;
+            }
+
+            if self.annotating[i] {
+                verdict = Verdict::Annotate;
+                continue;
+            }
+
+            let rejections = &mut self.stats.rejections.borrow_mut()[i];
+            rejections.count += 1;
+
+            if self.record_paths {
+                rejections.paths.push(path.to_path_buf());
+            }
+
+            return Ok(Verdict::Exclude);
+        }
+
+        Ok(verdict)
     }
 }
 
 impl Default for FilterAggregate {
     fn default() -> Self {
-        FilterAggregate { filters: Vec::new() }
+        FilterAggregate {
+            filters: Vec::new(),
+            annotating: Vec::new(),
+            stats: FilterStats::default(),
+            record_paths: false,
+        }
     }
 }
 
 impl FileFilter for FilterAggregate {
-    fn filter(&self, path: &Path) -> Result {
-        for f in &self.filters {
-            if !try!(f.filter(path)) {
-                return Ok(false);
+    fn filter(&self, path: &Path, metadata: &Metadata) -> Result {
+        self.verdict(path, metadata).map(|verdict| verdict != Verdict::Exclude)
+    }
+
+    fn verdict(&self, path: &Path, metadata: &Metadata) -> result::Result<Verdict, Box<Error>> {
+        self.judge(path, metadata, false)
+    }
+
+    fn detached_verdict(&self,
+                        path: &Path,
+                        metadata: &Metadata)
+                        -> result::Result<Verdict, Box<Error>> {
+        self.judge(path, metadata, true)
+    }
+
+    /// Names the first filter excluding `path`, along with its own explanation if it has one.
+    /// If no filter excludes `path`, this names the first annotating filter rejecting it instead,
+    /// to explain why it is marked.
+    fn explain(&self, path: &Path, metadata: &Metadata) -> Option<String> {
+        let rejections = self.stats.rejections.borrow();
+        let mut marked_by = None;
+
+        for (i, (f, rejections)) in self.filters.iter().zip(rejections.iter()).enumerate() {
+            if let Ok(false) = f.filter(path, metadata) {
+                let reason = match f.explain(path, metadata) {
+                    Some(reason) => format!("{} ({})", rejections.name, reason),
+                    None => rejections.name.clone(),
+                };
+
+                if !self.annotating[i] {
+                    return Some(reason);
+                } else if marked_by.is_none() {
+                    marked_by = Some(reason);
+                }
             }
         }
-        Ok(true)
+
+        marked_by
     }
 }
 
@@ -90,6 +286,7 @@ impl GlobFilterBuilder {
         builder.build()
             .map(|set| GlobFilter {
                 pattern: set,
+                patterns: self.patterns.clone(),
                 invert: self.invert,
             })
             .map_err(From::from)
@@ -99,6 +296,7 @@ impl GlobFilterBuilder {
 /// Filter by glob pattern.
 pub struct GlobFilter {
     pattern: GlobSet,
+    patterns: Vec<String>,
     invert: bool,
 }
 
@@ -119,7 +317,7 @@ impl GlobFilter {
 
 
 impl FileFilter for GlobFilter {
-    fn filter(&self, path: &Path) -> Result {
+    fn filter(&self, path: &Path, _metadata: &Metadata) -> Result {
         let path = path.strip_prefix("./").unwrap_or(path);
         let is_match = self.pattern.is_match(path);
 
@@ -131,6 +329,21 @@ impl FileFilter for GlobFilter {
             }
         )
     }
+
+    fn explain(&self, path: &Path, _metadata: &Metadata) -> Option<String> {
+        let path = path.strip_prefix("./").unwrap_or(path);
+
+        if self.invert {
+            let matching: Vec<_> = self.pattern
+                .matches(path)
+                .into_iter()
+                .map(|i| self.patterns[i].as_str())
+                .collect();
+            Some(format!("matches excluded pattern {}", matching.join(", ")))
+        } else {
+            Some(format!("matches none of the patterns {}", self.patterns.join(", ")))
+        }
+    }
 }
 
 
@@ -161,30 +374,299 @@ impl GitignoreFilter {
 }
 
 impl FileFilter for GitignoreFilter {
-    fn filter(&self, path: &Path) -> Result {
+    fn filter(&self, path: &Path, _metadata: &Metadata) -> Result {
         // ./filename paths doesn't seem to work with should_ignore
         let path = try!(path.canonicalize());
         self.repo.status_should_ignore(&path)
             .map(|x| !x)
             .map_err(From::from)
     }
+
+    /// Files that aren't on disk can't be looked up in the repository, so they're included.
+    fn detached_verdict(&self,
+                        _path: &Path,
+                        _metadata: &Metadata)
+                        -> result::Result<Verdict, Box<Error>> {
+        Ok(Verdict::Include)
+    }
+
+    /// Points out the ignore file and line matching `path`.
+    ///
+    /// Git doesn't expose which rule matched, so the rules are evaluated here with an
+    /// approximation of the gitignore semantics. Global excludes are not taken into account.
+    fn explain(&self, path: &Path, metadata: &Metadata) -> Option<String> {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            Err(_) => return None,
+        };
+        let workdir = match self.repo.workdir().map(Path::canonicalize) {
+            Some(Ok(workdir)) => workdir,
+            _ => return None,
+        };
+
+        find_ignore_rule(&workdir, &path, metadata.is_dir())
+            .map(|rule| format!("{}:{}: {}", rule.file.display(), rule.line, rule.pattern))
+    }
+}
+
+/// A line in an ignore file.
+struct IgnoreRule {
+    file: PathBuf,
+    line: usize,
+    pattern: String,
+}
+
+/// Find the rule deciding that `path` is ignored, in the repository rooted at `workdir`. `is_dir`
+/// tells whether `path` is a directory.
+///
+/// Rules are read from `.git/info/exclude` and every `.gitignore` from `workdir` down to the
+/// parent of `path`. The last matching rule wins, and a negated rule means `path` is not ignored.
+fn find_ignore_rule(workdir: &Path, path: &Path, is_dir: bool) -> Option<IgnoreRule> {
+    let relative = match path.strip_prefix(workdir) {
+        Ok(relative) => relative,
+        Err(_) => return None,
+    };
+
+    // Ignore files paired with the directory their patterns are relative to, in increasing order
+    // of precedence
+    let mut ignore_files = vec![(workdir.join(".git/info/exclude"), workdir.to_path_buf())];
+    let mut dir = workdir.to_path_buf();
+    ignore_files.push((dir.join(".gitignore"), dir.clone()));
+
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            dir.push(component);
+            ignore_files.push((dir.join(".gitignore"), dir.clone()));
+        }
+    }
+
+    let mut found = None;
+
+    for (file_path, base) in ignore_files {
+        let file = match File::open(&file_path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        let relative = match path.strip_prefix(&base) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            if let Some(negated) = ignore_rule_matches(line.trim_right(), relative, is_dir) {
+                found = if negated {
+                    None
+                } else {
+                    Some(IgnoreRule {
+                        file: file_path.clone(),
+                        line: i + 1,
+                        pattern: line.trim_right().to_owned(),
+                    })
+                };
+            }
+        }
+    }
+
+    found
+}
+
+/// Whether the gitignore `rule` matches `relative`, or any of its ancestors since ignoring a
+/// directory ignores its contents. `is_dir` tells whether `relative` is a directory.
+///
+/// Returns `None` if the rule doesn't match, otherwise whether it's negated.
+fn ignore_rule_matches(rule: &str, relative: &Path, is_dir: bool) -> Option<bool> {
+    if rule.is_empty() || rule.starts_with('#') {
+        return None;
+    }
+
+    let (negated, rule) = if rule.starts_with('!') {
+        (true, &rule[1..])
+    } else {
+        (false, rule)
+    };
+    let dir_only = rule.ends_with('/');
+    let rule = rule.trim_right_matches('/');
+
+    // Patterns without a slash match at any depth, others are relative to the ignore file
+    let glob = if rule.contains('/') {
+        rule.trim_left_matches('/').to_owned()
+    } else {
+        format!("**/{}", rule)
+    };
+    let matcher = match GlobBuilder::new(&glob).literal_separator(true).build() {
+        Ok(glob) => glob.compile_matcher(),
+        Err(_) => return None,
+    };
+
+    let mut candidate = Some(relative);
+
+    while let Some(current) = candidate {
+        if current.as_os_str().is_empty() {
+            break;
+        }
+
+        // Ancestors of the entry are always directories
+        let is_dir = current != relative || is_dir;
+
+        if matcher.is_match(current) && (is_dir || !dir_only) {
+            return Some(negated);
+        }
+
+        candidate = current.parent();
+    }
+
+    None
 }
 
 /// Exclude hidden files.
 ///
 /// This function relies on the Unix convention of denoting hidden files with a leading dot (`.`).
-pub fn filter_hidden_files(path: &Path) -> Result {
+pub fn filter_hidden_files(path: &Path, _metadata: &Metadata) -> Result {
     path.file_name()
-        .and_then(|name| {
-            name.to_str()
-                .map(|str| !str.starts_with('.'))
-            })
+        .map(|name| !name.to_string_lossy().starts_with('.'))
         .ok_or_else(|| From::from("No file name."))
 }
 
 /// Exclude non directory files.
-pub fn filter_non_dirs(path: &Path) -> Result {
-    path.metadata()
-        .map(|data| data.is_dir())
-        .map_err(From::from)
+///
+/// Symlinks are followed on disk, so links to directories are kept. Symlinks whose target can't
+/// be read, like dangling ones or those of replayed trees, are excluded.
+pub fn filter_non_dirs(path: &Path, metadata: &Metadata) -> Result {
+    if metadata.file_type().is_symlink() {
+        Ok(FsSource.target_metadata(path).map(|target| target.is_dir()).unwrap_or(false))
+    } else {
+        Ok(metadata.is_dir())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use super::*;
+    use super::super::metadata::{FileType, Metadata};
+    use super::super::source::MemorySource;
+    use super::super::tree::{Event, EventSource, TreeIter};
+
+    fn file() -> Metadata {
+        Metadata::new(FileType::File)
+    }
+
+    fn globs(patterns: &[&str], invert: bool) -> GlobFilter {
+        GlobFilter::from(patterns.iter().map(|pattern| String::from(*pattern)), invert).unwrap()
+    }
+
+    #[test]
+    fn hidden_files() {
+        assert!(!filter_hidden_files(Path::new("./.git"), &file()).unwrap());
+        assert!(filter_hidden_files(Path::new("./src/.."), &file()).is_err());
+        assert!(filter_hidden_files(Path::new("./src"), &file()).unwrap());
+    }
+
+    #[test]
+    fn non_dirs() {
+        assert!(filter_non_dirs(Path::new("./src"), &Metadata::new(FileType::Dir)).unwrap());
+        assert!(!filter_non_dirs(Path::new("./main.c"), &file()).unwrap());
+
+        let dir = env::temp_dir().join(format!("oak-filters-test-{}", process::id()));
+        fs::create_dir_all(dir.join("dir")).unwrap();
+        File::create(dir.join("file")).unwrap();
+        unix::fs::symlink("dir", dir.join("dir-link")).unwrap();
+        unix::fs::symlink("file", dir.join("file-link")).unwrap();
+        unix::fs::symlink("missing", dir.join("dangling")).unwrap();
+
+        let link = Metadata::new(FileType::Symlink);
+        let included = |name| filter_non_dirs(&dir.join(name), &link).unwrap();
+        let judged = (included("dir-link"), included("file-link"), included("dangling"));
+        let _ = fs::remove_dir_all(&dir);
+
+        // Links are followed to their targets
+        assert_eq!(judged, (true, false, false));
+    }
+
+    #[test]
+    fn include_globs() {
+        let filter = globs(&["*.rs", "Cargo.*"], false);
+
+        assert!(filter.filter(Path::new("./src/main.rs"), &file()).unwrap());
+        assert!(filter.filter(Path::new("Cargo.toml"), &file()).unwrap());
+        assert!(!filter.filter(Path::new("./README.md"), &file()).unwrap());
+        assert_eq!(filter.explain(Path::new("./README.md"), &file()),
+                   Some(String::from("matches none of the patterns *.rs, Cargo.*")));
+    }
+
+    #[test]
+    fn exclude_globs() {
+        let filter = globs(&["target", "*.o"], true);
+
+        assert!(!filter.filter(Path::new("./target"), &file()).unwrap());
+        assert!(filter.filter(Path::new("./src"), &file()).unwrap());
+        assert_eq!(filter.explain(Path::new("./main.o"), &file()),
+                   Some(String::from("matches excluded pattern *.o")));
+    }
+
+    #[test]
+    fn aggregate_verdicts() {
+        let mut filters = FilterAggregate::default();
+        filters.push_named("exclude glob", globs(&["*.o"], true));
+        filters.push_annotating("hidden", filter_hidden_files);
+
+        let verdict = |path: &str| filters.verdict(Path::new(path), &file()).unwrap();
+
+        assert_eq!(verdict("./main.c"), Verdict::Include);
+        assert_eq!(verdict("./.main.c"), Verdict::Annotate);
+        assert_eq!(verdict("./.main.o"), Verdict::Exclude);
+        assert_eq!(filters.explain(Path::new("./.main.c"), &file()), Some(String::from("hidden")));
+        assert_eq!(filters.explain(Path::new("./main.c"), &file()), None);
+
+        let mut reversed = FilterAggregate::default();
+        reversed.push_annotating("hidden", filter_hidden_files);
+        reversed.push_named("exclude glob", globs(&["*.o"], true));
+
+        assert_eq!(reversed.verdict(Path::new("./.main.o"), &file()).unwrap(), Verdict::Exclude);
+        assert_eq!(reversed.explain(Path::new("./.main.o"), &file()),
+                   Some(String::from("exclude glob (matches excluded pattern *.o)")));
+        assert_eq!(reversed.explain(Path::new("./.main.c"), &file()), Some(String::from("hidden")));
+    }
+
+    #[test]
+    fn stats() {
+        let mut filters = FilterAggregate::default();
+        filters.push_named("hidden", filter_hidden_files);
+        filters.push_named("exclude glob", globs(&["*.o"], true));
+        filters.record_paths(true);
+
+        let stats = filters.stats();
+        let source = MemorySource::parse(".", "
+            .cache/objects/main.o
+            src/main.c
+            src/main.o
+            src/.main.o
+        ");
+        let mut tree_iter = TreeIter::with_source(source, ".", filters).unwrap();
+        let num_files = tree_iter.by_ref()
+            .filter(|event| match *event {
+                Ok(Event::File(_)) => true,
+                _ => false,
+            })
+            .count();
+
+        assert_eq!(num_files, 1);
+        assert_eq!(tree_iter.num_filtered(), 3);
+        assert_eq!(stats.total(), 3);
+
+        let rejections = stats.rejections();
+        assert_eq!(rejections[0].count, 2);
+        assert_eq!(rejections[0].paths,
+                   [PathBuf::from("./.cache"), PathBuf::from("./src/.main.o")]);
+        assert_eq!(rejections[1].paths, [PathBuf::from("./src/main.o")]);
+    }
 }
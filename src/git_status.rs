@@ -0,0 +1,82 @@
+//! Looking up the git status of files.
+
+extern crate git2;
+
+use std::error::Error;
+use std::path::Path;
+use std::result;
+use self::git2::Repository;
+
+/// Looks up the status of files in a git repository.
+pub struct GitStatus {
+    repo: Repository,
+}
+
+impl GitStatus {
+    /// Create a lookup for the repository containing `path`.
+    ///
+    /// Returns `None` if `path` isn't in a repository.
+    pub fn new(path: &Path) -> Option<result::Result<GitStatus, Box<Error>>> {
+        match Repository::discover(path) {
+            Ok(repo) => Some(Ok(GitStatus { repo: repo })),
+            Err(err) => {
+                if err.code() == git2::ErrorCode::NotFound {
+                    None
+                } else {
+                    Some(Err(From::from(err)))
+                }
+            },
+        }
+    }
+
+    /// The status of the file at `path` as a two letter code, like `git status --short`.
+    ///
+    /// The first letter is the status in the index and the second in the work tree, e.g. `" M"`
+    /// for a modified file or `"??"` for an untracked file. Unmodified files get `"  "`.
+    pub fn status(&self, path: &Path) -> result::Result<String, Box<Error>> {
+        let path = try!(path.canonicalize());
+        let workdir = match self.repo.workdir() {
+            Some(workdir) => try!(workdir.canonicalize()),
+            None => return Err(From::from("Repository has no work tree")),
+        };
+        let relative = try!(path.strip_prefix(&workdir));
+
+        let status = try!(self.repo.status_file(relative));
+
+        if status.contains(git2::STATUS_IGNORED) {
+            return Ok(From::from("!!"));
+        }
+
+        if status.contains(git2::STATUS_WT_NEW) {
+            return Ok(From::from("??"));
+        }
+
+        let index = if status.contains(git2::STATUS_INDEX_NEW) {
+            'A'
+        } else if status.contains(git2::STATUS_INDEX_MODIFIED) {
+            'M'
+        } else if status.contains(git2::STATUS_INDEX_DELETED) {
+            'D'
+        } else if status.contains(git2::STATUS_INDEX_RENAMED) {
+            'R'
+        } else if status.contains(git2::STATUS_INDEX_TYPECHANGE) {
+            'T'
+        } else {
+            ' '
+        };
+
+        let work_tree = if status.contains(git2::STATUS_WT_MODIFIED) {
+            'M'
+        } else if status.contains(git2::STATUS_WT_DELETED) {
+            'D'
+        } else if status.contains(git2::STATUS_WT_RENAMED) {
+            'R'
+        } else if status.contains(git2::STATUS_WT_TYPECHANGE) {
+            'T'
+        } else {
+            ' '
+        };
+
+        Ok(format!("{}{}", index, work_tree))
+    }
+}
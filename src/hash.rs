@@ -0,0 +1,239 @@
+//! Hashing file contents.
+
+extern crate blake3;
+extern crate sha2;
+extern crate xxhash_rust;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use self::sha2::{Digest, Sha256};
+use self::xxhash_rust::xxh3::Xxh3;
+use super::metadata::Metadata;
+use super::source::{FsSource, TreeSource};
+use super::tree::Entry;
+
+/// Size of the buffer files are read with.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A hash algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    /// The 64 bit variant of XXH3. Fast, but not cryptographically secure.
+    Xxh3,
+}
+
+/// All algorithms.
+pub const ALL_ALGORITHMS: [Algorithm; 3] = [Algorithm::Sha256, Algorithm::Blake3, Algorithm::Xxh3];
+
+impl Algorithm {
+    /// The algorithm's name, e.g. `sha256`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    /// Look up an algorithm by its name.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        ALL_ALGORITHMS.iter().cloned().find(|algorithm| algorithm.name() == name)
+    }
+}
+
+/// An incremental hash computation.
+pub struct Hasher {
+    state: State,
+}
+
+enum State {
+    Sha256(Sha256),
+    Blake3(blake3::Hasher),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    /// Start a new computation with `algorithm`.
+    pub fn new(algorithm: Algorithm) -> Self {
+        let state = match algorithm {
+            Algorithm::Sha256 => State::Sha256(Sha256::new()),
+            Algorithm::Blake3 => State::Blake3(blake3::Hasher::new()),
+            Algorithm::Xxh3 => State::Xxh3(Box::new(Xxh3::new())),
+        };
+
+        Hasher { state: state }
+    }
+
+    /// Add `data` to the input.
+    pub fn update(&mut self, data: &[u8]) {
+        match self.state {
+            State::Sha256(ref mut hasher) => hasher.update(data),
+            State::Blake3(ref mut hasher) => {
+                hasher.update(data);
+            },
+            State::Xxh3(ref mut hasher) => hasher.update(data),
+        }
+    }
+
+    /// The digest of the input.
+    pub fn finish(self) -> Vec<u8> {
+        match self.state {
+            State::Sha256(hasher) => hasher.finalize().to_vec(),
+            State::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            State::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// The digest of the contents of the file at `path`, which is read in chunks.
+pub fn file_digest(path: &Path, algorithm: Algorithm) -> io::Result<Vec<u8>> {
+    reader_digest(try!(File::open(path)), algorithm)
+}
+
+/// The digest of everything read from `reader`, which is read in chunks.
+pub fn reader_digest<R: Read>(mut reader: R, algorithm: Algorithm) -> io::Result<Vec<u8>> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; BUFFER_SIZE];
+
+    loop {
+        let num_read = try!(reader.read(&mut buffer));
+
+        if num_read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..num_read]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// The digest of an entry: the contents of regular files or the target path of symlinks.
+///
+/// Other types of files have no digest.
+pub fn entry_digest(path: &Path,
+                    metadata: &Metadata,
+                    algorithm: Algorithm)
+                    -> io::Result<Option<Vec<u8>>> {
+    source_digest(&FsSource, path, metadata, algorithm)
+}
+
+/// Like `entry_digest()`, for an entry of `source`.
+pub fn source_digest(source: &TreeSource,
+                     path: &Path,
+                     metadata: &Metadata,
+                     algorithm: Algorithm)
+                     -> io::Result<Option<Vec<u8>>> {
+    if metadata.is_file() {
+        reader_digest(try!(source.open(path)), algorithm).map(Some)
+    } else if metadata.file_type().is_symlink() {
+        let target = try!(source.read_link(path));
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(target.as_os_str().as_bytes());
+        Ok(Some(hasher.finish()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Format `digest` as lowercase hexadecimal.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parse a digest formatted by `to_hex()`.
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, Box<Error>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(From::from(format!("Invalid digest '{}'", hex)));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(From::from))
+        .collect()
+}
+
+/// Combines the digests of entries into Merkle-style digests of their directories, following the
+/// events of a walk.
+///
+/// A directory's digest is the digest of a line per entry, sorted by name, holding the entry's
+/// type, its hex digest or `-` and its name. A change anywhere within a directory thus changes
+/// its digest, as well as the digests of its ancestors.
+pub struct MerkleStack {
+    algorithm: Algorithm,
+    /// The open directories with the name of each, and the lines of their entries so far. The
+    /// root is at the bottom.
+    dirs: Vec<(Vec<u8>, Vec<Vec<u8>>)>,
+}
+
+impl MerkleStack {
+    /// Create a new stack, with the root directory open.
+    pub fn new(algorithm: Algorithm) -> Self {
+        MerkleStack {
+            algorithm: algorithm,
+            dirs: vec![(Vec::new(), Vec::new())],
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Enter a directory, in response to `OpenDir`.
+    pub fn open_dir(&mut self, entry: &Entry) {
+        self.dirs.push((file_name(entry).to_vec(), Vec::new()));
+    }
+
+    /// Add an entry that isn't descended into to the current directory.
+    pub fn file(&mut self, entry: &Entry) {
+        let line = entry_line(entry.metadata().file_type().name(),
+                              entry.digest(),
+                              file_name(entry));
+
+        if let Some(&mut (_, ref mut lines)) = self.dirs.last_mut() {
+            lines.push(line);
+        }
+    }
+
+    /// Leave the current directory, in response to `CloseDir`, returning its digest.
+    pub fn close_dir(&mut self) -> Vec<u8> {
+        let (name, mut lines) = self.dirs.pop().unwrap_or_default();
+        lines.sort_by(|a, b| line_name(a).cmp(line_name(b)));
+
+        let mut hasher = Hasher::new(self.algorithm);
+
+        for line in &lines {
+            hasher.update(line);
+        }
+
+        let digest = hasher.finish();
+
+        if let Some(&mut (_, ref mut parent_lines)) = self.dirs.last_mut() {
+            parent_lines.push(entry_line("directory", Some(&digest), &name));
+        }
+
+        digest
+    }
+}
+
+fn file_name(entry: &Entry) -> &[u8] {
+    entry.path().file_name().map(|name| name.as_bytes()).unwrap_or_default()
+}
+
+fn entry_line(type_name: &str, digest: Option<&[u8]>, name: &[u8]) -> Vec<u8> {
+    let digest = digest.map(to_hex).unwrap_or_else(|| String::from("-"));
+    let mut line = format!("{} {} ", type_name, digest).into_bytes();
+    line.extend_from_slice(name);
+    line.push(b'\n');
+    line
+}
+
+/// The name in a line made by `entry_line()`.
+fn line_name(line: &[u8]) -> &[u8] {
+    line.splitn(3, |&byte| byte == b' ').nth(2).unwrap_or(line)
+}
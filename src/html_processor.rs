@@ -0,0 +1,220 @@
+//! `HtmlProcessor` and supporting types.
+//!
+//! This processor outputs a self-contained HTML document, where directories can be collapsed and
+//! expanded using `<details>` elements.
+
+use std::path::{Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+use super::columns::{self, DEFAULT_TIME_FORMAT};
+
+const STYLE: &'static str = "\
+body { font-family: sans-serif; }
+ul.tree, ul.tree ul { list-style: none; padding-left: 1.5em; }
+ul.tree summary { cursor: pointer; font-weight: bold; }
+ul.tree .size, ul.tree .date { color: #777; margin-left: 1em; }
+ul.tree .size { font-family: monospace; }
+ul.tree .ignored { opacity: 0.5; }";
+
+/// Builder for `HtmlProcessor`.
+///
+/// Like `PrintProcessorBuilder`, nothing is printed until the processor is built.
+pub struct HtmlProcessorBuilder {
+    root: PathBuf,
+    base_href: Option<String>,
+    time_format: String,
+    open: bool,
+}
+
+impl HtmlProcessorBuilder {
+    /// Create a new builder.
+    pub fn new(root: PathBuf) -> Self {
+        HtmlProcessorBuilder {
+            root: root,
+            base_href: None,
+            time_format: From::from(DEFAULT_TIME_FORMAT),
+            open: true,
+        }
+    }
+
+    /// Link each file to its path, relative to the root, appended to `base_href`.
+    pub fn base_href(&mut self, base_href: String) -> &mut Self {
+        self.base_href = Some(base_href);
+        self
+    }
+
+    /// Set the `strftime` style format of modification dates.
+    pub fn time_format(&mut self, format: String) -> &mut Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Whether directories are initially expanded.
+    pub fn open(&mut self, open: bool) -> &mut Self {
+        self.open = open;
+        self
+    }
+
+    /// Build an `HtmlProcessor`.
+    ///
+    /// This method also prints the head of the document.
+    pub fn build(&self) -> HtmlProcessor {
+        let title = escape(&self.root.to_string_lossy());
+
+        println!("<!DOCTYPE html>");
+        println!("<html>");
+        println!("<head>");
+        println!("<meta charset=\"utf-8\">");
+        println!("<title>{}</title>", title);
+        println!("<style>\n{}\n</style>", STYLE);
+        println!("</head>");
+        println!("<body>");
+        println!("<h1>{}</h1>", title);
+        println!("<ul class=\"tree\">");
+
+        HtmlProcessor {
+            root: self.root.clone(),
+            base_href: self.base_href.clone(),
+            time_format: self.time_format.clone(),
+            open: self.open,
+            depth: 1,
+            num_dirs: 0,
+            num_files: 0,
+        }
+    }
+}
+
+/// A `TreeProcessor` printing the tree as an HTML document of nested lists.
+pub struct HtmlProcessor {
+    root: PathBuf,
+    base_href: Option<String>,
+    time_format: String,
+    open: bool,
+    depth: usize,
+    num_dirs: usize,
+    num_files: usize,
+}
+
+impl HtmlProcessor {
+    fn indent(&self) -> String {
+        "  ".repeat(self.depth)
+    }
+
+    fn date(&self, entry: &Entry) -> String {
+        format!("<span class=\"date\">{}</span>",
+                escape(&columns::format_time(entry.metadata().mtime(), &self.time_format)))
+    }
+
+    /// The opening `<li>` tag for `entry`.
+    fn item(&self, entry: &Entry) -> String {
+        if entry.is_ignored() {
+            format!("{}<li class=\"ignored\">", self.indent())
+        } else {
+            format!("{}<li>", self.indent())
+        }
+    }
+
+    fn link(&self, entry: &Entry) -> String {
+        let name = escape(&entry.file_name());
+
+        match self.base_href {
+            Some(ref base_href) => {
+                let relative = entry.path().strip_prefix(&self.root).unwrap_or(entry.path());
+                format!("<a href=\"{}{}\">{}</a>",
+                        escape(base_href),
+                        escape(&url_encode(relative)),
+                        name)
+            },
+            None => name,
+        }
+    }
+}
+
+impl TreeProcessor for HtmlProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        println!("{}<details{}><summary>{}{}</summary>",
+                 self.item(entry),
+                 if self.open { " open" } else { "" },
+                 escape(&entry.file_name()),
+                 self.date(entry));
+        self.depth += 1;
+        println!("{}<ul>", self.indent());
+        self.num_dirs += 1;
+    }
+
+    fn close_dir(&mut self) {
+        if self.depth > 1 {
+            println!("{}</ul>", self.indent());
+            self.depth -= 1;
+            println!("{}</details></li>", self.indent());
+        }
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        println!("{}{}<span class=\"size\">{}</span>{}</li>",
+                 self.item(entry),
+                 self.link(entry),
+                 entry.metadata().len(),
+                 self.date(entry));
+        self.num_files += 1;
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        println!("{}{} [{} entries exceeds filelimit]{}</li>",
+                 self.item(entry),
+                 escape(&entry.file_name()),
+                 num_entries,
+                 self.date(entry));
+        self.num_dirs += 1;
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        println!("{}<li>… {} more</li>", self.indent(), num_entries);
+    }
+
+    fn finish(&mut self, _tree: &EventSource) {
+        println!("</ul>");
+        println!("<p>{} {}, {} {}</p>",
+                 self.num_dirs,
+                 if self.num_dirs == 1 { "directory" } else { "directories" },
+                 self.num_files,
+                 if self.num_files == 1 { "file" } else { "files" });
+        println!("</body>");
+        println!("</html>");
+    }
+}
+
+/// Escape the characters with special meaning in HTML.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Percent-encode a relative path for use in a URL, keeping the separators.
+pub fn url_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            },
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
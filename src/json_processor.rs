@@ -0,0 +1,193 @@
+//! `JsonProcessor` and supporting types.
+//!
+//! The output resembles the JSON format of the classic `tree` utility (`tree -J`), extended with
+//! the metadata needed to replay it with `json_replay::JsonTree`.
+
+extern crate serde_json;
+
+use std::path::PathBuf;
+use self::serde_json::Value;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+use super::hash::{self, Algorithm, MerkleStack};
+
+/// Builder for `JsonProcessor`.
+pub struct JsonProcessorBuilder {
+    root: PathBuf,
+    digests: Option<Algorithm>,
+}
+
+impl JsonProcessorBuilder {
+    /// Create a new builder.
+    pub fn new(root: PathBuf) -> Self {
+        JsonProcessorBuilder {
+            root: root,
+            digests: None,
+        }
+    }
+
+    /// Add a Merkle-style digest to each directory, combining the digests of its entries with
+    /// `algorithm`. See `hash::MerkleStack`.
+    ///
+    /// The entries should be hashed with the same algorithm, see `TreeIter::hash()`.
+    pub fn digests(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.digests = Some(algorithm);
+        self
+    }
+
+    /// Build a `JsonProcessor`.
+    ///
+    /// This method also prints the opening of the root directory object.
+    pub fn build(&self) -> JsonProcessor {
+        println!("[");
+        println!("  {{\"type\":\"directory\",\"name\":{},\"contents\":[",
+                 quote(&self.root.to_string_lossy()));
+
+        JsonProcessor {
+            // The root is followed by the report
+            dir_has_next: vec![true],
+            num_dirs: 0,
+            num_files: 0,
+            merkle: self.digests.map(MerkleStack::new),
+        }
+    }
+}
+
+/// A `TreeProcessor` printing the tree as JSON.
+///
+/// The tree is an array holding the root directory followed by a report. Each entry is an object
+/// with its type, name and metadata, where directories list their entries in `contents`.
+/// Directories that weren't descended into have an `entries` count instead, and entries left out
+/// of a directory are represented by an object of type `elided`. Names are lossily converted to
+/// UTF-8.
+///
+/// Entries with a digest have it in hex in `digest`, and with `JsonProcessorBuilder::digests()`,
+/// so do directories. Since a directory's digest depends on its contents, it follows them.
+///
+/// # Example
+///
+/// ```text
+/// [
+///   {"type":"directory","name":".","contents":[
+///     {"type":"directory","name":"b","size":4096,"mode":"0755","mtime":1476871200,...,"contents":[
+///       {"type":"file","name":"1","size":12,"mode":"0644","mtime":1476871200,...}
+///     ]}
+///   ]},
+///   {"type":"report","directories":1,"files":1}
+/// ]
+/// ```
+pub struct JsonProcessor {
+    /// Whether each open directory has a next sibling, innermost last.
+    dir_has_next: Vec<bool>,
+    num_dirs: usize,
+    num_files: usize,
+    merkle: Option<MerkleStack>,
+}
+
+impl JsonProcessor {
+    fn indent(&self) -> String {
+        "  ".repeat(self.dir_has_next.len() + 1)
+    }
+
+    /// The members common to all objects representing entries, without braces.
+    fn members(&self, entry: &Entry) -> String {
+        let metadata = entry.metadata();
+        let mut members = format!("\"type\":{},\"name\":{},\"size\":{},\"mode\":\"{:04o}\",\
+                                   \"mtime\":{},\"uid\":{},\"gid\":{},\"ino\":{},\"dev\":{}",
+                                  quote(metadata.file_type().name()),
+                                  quote(&entry.file_name()),
+                                  metadata.len(),
+                                  metadata.mode(),
+                                  metadata.mtime(),
+                                  metadata.uid(),
+                                  metadata.gid(),
+                                  metadata.ino(),
+                                  metadata.dev());
+
+        if entry.is_ignored() {
+            members.push_str(",\"ignored\":true");
+        }
+
+        if entry.is_archive_member() {
+            members.push_str(",\"archive_member\":true");
+        }
+
+        if entry.is_mount_point() {
+            members.push_str(",\"mount_point\":true");
+        }
+
+        if let Some(digest) = entry.digest() {
+            members.push_str(&format!(",\"digest\":\"{}\"", hash::to_hex(digest)));
+        }
+
+        members
+    }
+}
+
+impl TreeProcessor for JsonProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        println!("{}{{{},\"contents\":[", self.indent(), self.members(entry));
+        self.dir_has_next.push(entry.has_next_sibling());
+        self.num_dirs += 1;
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.open_dir(entry);
+        }
+    }
+
+    fn close_dir(&mut self) {
+        let has_next = self.dir_has_next.pop().unwrap_or(false);
+        let digest = match self.merkle {
+            Some(ref mut merkle) => format!(",\"digest\":\"{}\"", hash::to_hex(&merkle.close_dir())),
+            None => String::new(),
+        };
+
+        println!("{}]{}}}{}", self.indent(), digest, separator(has_next));
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        println!("{}{{{}}}{}",
+                 self.indent(),
+                 self.members(entry),
+                 separator(entry.has_next_sibling()));
+        self.num_files += 1;
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        println!("{}{{{},\"entries\":{}}}{}",
+                 self.indent(),
+                 self.members(entry),
+                 num_entries,
+                 separator(entry.has_next_sibling()));
+        self.num_dirs += 1;
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        // Always the last entry of its directory
+        println!("{}{{\"type\":\"elided\",\"count\":{}}}", self.indent(), num_entries);
+    }
+
+    fn finish(&mut self, _tree: &EventSource) {
+        println!("  {{\"type\":\"report\",\"directories\":{},\"files\":{}}}",
+                 self.num_dirs,
+                 self.num_files);
+        println!("]");
+    }
+}
+
+/// Quote `text` as a JSON string.
+fn quote(text: &str) -> String {
+    Value::String(From::from(text)).to_string()
+}
+
+fn separator(has_next: bool) -> &'static str {
+    if has_next { "," } else { "" }
+}
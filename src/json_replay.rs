@@ -0,0 +1,164 @@
+//! Replaying saved JSON listings.
+//!
+//! A listing written by `json_processor::JsonProcessor` can be read back with `JsonTree`, and
+//! walked by `TreeIter` through `replay::NodeSource` without touching the file system. This allows
+//! any `TreeProcessor` to render a snapshot taken earlier.
+
+extern crate serde_json;
+
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use self::serde_json::{Map, Value};
+use super::hash;
+use super::metadata::{FileType, Metadata};
+use super::replay::{Node, NodeInfo, NodeSource};
+
+/// A tree read from a JSON listing.
+pub struct JsonTree {
+    root: PathBuf,
+    source: NodeSource,
+}
+
+impl JsonTree {
+    /// Read a listing in the format written by `JsonProcessor`.
+    ///
+    /// Metadata missing from the listing defaults to zero, so the output of `tree -J` can be read
+    /// as well. Entries must be named by a single file name, unique within their directory.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Box<Error>> {
+        let value: Value = try!(serde_json::from_reader(reader));
+        let items = try!(value.as_array().ok_or("Expected an array at the top level"));
+
+        let root = try!(items.iter()
+            .find(|item| item.get("type").and_then(Value::as_str) == Some("directory"))
+            .ok_or("No root directory in listing"));
+
+        match try!(parse_node(root)) {
+            Node::Dir(info, contents) => {
+                let root = PathBuf::from(info.name);
+
+                Ok(JsonTree {
+                    source: try!(NodeSource::new(root.clone(), contents)),
+                    root: root,
+                })
+            },
+            _ => Err(From::from("The root directory has no contents")),
+        }
+    }
+
+    /// The path of the root directory, as given when the listing was made.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The tree as a source for `TreeIter`.
+    pub fn into_source(self) -> NodeSource {
+        self.source
+    }
+}
+
+fn parse_node(value: &Value) -> Result<Node, Box<Error>> {
+    let object = try!(value.as_object().ok_or("Expected an object for each entry"));
+    let type_name = try!(object.get("type")
+        .and_then(Value::as_str)
+        .ok_or("Entry without a type"));
+
+    if type_name == "elided" {
+        return Ok(Node::Elided(try!(get_u64(object, "count")).unwrap_or(0) as usize));
+    }
+
+    let file_type = try!(FileType::from_name(type_name)
+        .ok_or_else(|| format!("Unknown entry type '{}'", type_name)));
+    let name = try!(object.get("name")
+        .and_then(Value::as_str)
+        .ok_or("Entry without a name"));
+
+    let mut metadata = Metadata::new(file_type);
+    metadata.len = try!(get_u64(object, "size")).unwrap_or(0);
+    metadata.mtime = match object.get("mtime") {
+        Some(mtime) => try!(mtime.as_i64().ok_or("Expected a number for 'mtime'")),
+        None => 0,
+    };
+    metadata.uid = try!(get_u64(object, "uid")).unwrap_or(0) as u32;
+    metadata.gid = try!(get_u64(object, "gid")).unwrap_or(0) as u32;
+    metadata.ino = try!(get_u64(object, "ino")).unwrap_or(0);
+    metadata.dev = try!(get_u64(object, "dev")).unwrap_or(0);
+
+    if let Some(mode) = object.get("mode") {
+        metadata.mode = try!(mode.as_str()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or_else(|| format!("Invalid mode of '{}'", name)));
+    }
+
+    let digest = match object.get("digest") {
+        // Directory digests are computed from their contents rather than replayed
+        Some(_) if file_type.is_dir() => None,
+        Some(digest) => {
+            let digest = try!(digest.as_str().ok_or("Expected a string for 'digest'"));
+            Some(try!(hash::from_hex(digest)))
+        },
+        None => None,
+    };
+
+    let info = NodeInfo {
+        name: From::from(name),
+        metadata: metadata,
+        ignored: object.get("ignored").and_then(Value::as_bool).unwrap_or(false),
+        digest: digest,
+        archive_member: object.get("archive_member").and_then(Value::as_bool).unwrap_or(false),
+        mount_point: object.get("mount_point").and_then(Value::as_bool).unwrap_or(false),
+    };
+
+    if let Some(contents) = object.get("contents") {
+        let contents = try!(contents.as_array()
+            .ok_or_else(|| format!("Contents of '{}' is not an array", name)));
+        let nodes = try!(contents.iter().map(parse_node).collect());
+        Ok(Node::Dir(info, nodes))
+    } else if let Some(num_entries) = try!(get_u64(object, "entries")) {
+        Ok(Node::LimitedDir(info, num_entries as usize))
+    } else if file_type.is_dir() {
+        Ok(Node::Dir(info, Vec::new()))
+    } else {
+        Ok(Node::File(info))
+    }
+}
+
+/// The unsigned integer member `key` of `object`, if present.
+fn get_u64(object: &Map<String, Value>, key: &str) -> Result<Option<u64>, Box<Error>> {
+    match object.get(key) {
+        Some(value) => {
+            value.as_u64()
+                .map(Some)
+                .ok_or_else(|| From::from(format!("Expected a number for '{}'", key)))
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(json: &str) -> String {
+        match JsonTree::from_reader(json.as_bytes()) {
+            Ok(_) => String::from("no error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        let listing = |names: &[&str]| {
+            let contents: Vec<_> = names.iter()
+                .map(|name| format!(r#"{{"type": "file", "name": "{}"}}"#, name))
+                .collect();
+            format!(r#"[{{"type": "directory", "name": ".", "contents": [{}]}}]"#,
+                    contents.join(", "))
+        };
+
+        assert_eq!(error(&listing(&["a", "b"])), "no error");
+        assert_eq!(error(&listing(&[""])), "Invalid entry name '' in '.'");
+        assert_eq!(error(&listing(&[".."])), "Invalid entry name '..' in '.'");
+        assert_eq!(error(&listing(&["a", "a"])), "Duplicate entry './a'");
+    }
+}
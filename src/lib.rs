@@ -2,3 +2,24 @@ pub mod print_processor;
 pub mod tree_processor;
 pub mod tree;
 pub mod filters;
+pub mod columns;
+pub mod report;
+pub mod html_processor;
+pub mod xml_processor;
+pub mod markdown_processor;
+pub mod dot_processor;
+pub mod list_processor;
+pub mod git_status;
+pub mod csv_processor;
+pub mod metadata;
+pub mod json_processor;
+pub mod replay;
+pub mod json_replay;
+pub mod archive;
+pub mod hash;
+pub mod diff;
+pub mod snapshot;
+pub mod duplicates_processor;
+pub mod watch;
+pub mod browser;
+pub mod source;
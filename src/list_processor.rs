@@ -0,0 +1,106 @@
+//! `ListProcessor` and supporting types.
+//!
+//! This processor prints a flat listing of paths, one per line, for consumption by other
+//! programs. Paths are written as raw bytes, so names that aren't valid UTF-8 survive intact.
+
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+use super::columns;
+
+/// Builder for `ListProcessor`.
+pub struct ListProcessorBuilder {
+    null_terminated: bool,
+    classify: bool,
+}
+
+impl ListProcessorBuilder {
+    /// Create a new builder.
+    pub fn new() -> Self {
+        ListProcessorBuilder {
+            null_terminated: false,
+            classify: false,
+        }
+    }
+
+    /// Terminate paths with NUL instead of newline, like `find -print0`.
+    pub fn null_terminated(&mut self, null_terminated: bool) -> &mut Self {
+        self.null_terminated = null_terminated;
+        self
+    }
+
+    /// Append a file type indicator to each path. See `columns::indicator()`.
+    pub fn classify(&mut self, classify: bool) -> &mut Self {
+        self.classify = classify;
+        self
+    }
+
+    /// Build a `ListProcessor`.
+    pub fn build(&self) -> ListProcessor {
+        ListProcessor {
+            out: BufWriter::new(io::stdout()),
+            terminator: if self.null_terminated { b'\0' } else { b'\n' },
+            classify: self.classify,
+        }
+    }
+}
+
+impl Default for ListProcessorBuilder {
+    fn default() -> Self {
+        ListProcessorBuilder::new()
+    }
+}
+
+/// A `TreeProcessor` printing the path of each entry on a line of its own.
+///
+/// Paths are printed as walked, i.e. prefixed with the root, except that a leading `./` is left
+/// out. Directories are listed before their contents.
+pub struct ListProcessor {
+    out: BufWriter<io::Stdout>,
+    terminator: u8,
+    classify: bool,
+}
+
+impl ListProcessor {
+    fn print_path(&mut self, entry: &Entry) {
+        let path = entry.path();
+        let path = path.strip_prefix(".").unwrap_or(path);
+
+        // Write errors, such as a closed pipe, can't be reported through `TreeProcessor`
+        let _ = self.write_path(path, entry);
+    }
+
+    fn write_path(&mut self, path: &Path, entry: &Entry) -> io::Result<()> {
+        try!(self.out.write_all(path.as_os_str().as_bytes()));
+
+        if self.classify {
+            if let Some(indicator) = columns::indicator(entry.metadata()) {
+                try!(write!(self.out, "{}", indicator));
+            }
+        }
+
+        self.out.write_all(&[self.terminator])
+    }
+}
+
+impl TreeProcessor for ListProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        self.print_path(entry);
+    }
+
+    fn close_dir(&mut self) {}
+
+    fn file(&mut self, entry: &Entry) {
+        self.print_path(entry);
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, _num_entries: usize) {
+        self.print_path(entry);
+    }
+
+    fn finish(&mut self, _tree: &EventSource) {
+        let _ = self.out.flush();
+    }
+}
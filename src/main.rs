@@ -1,32 +1,513 @@
 #[macro_use]
 extern crate clap;
+extern crate libc;
 extern crate oak;
 
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::io::{Write, stderr};
 use std::fmt::Display;
 use oak::tree_processor::TreeProcessor;
 use oak::print_processor::{PrintProcessorBuilder, SummaryFormat};
-use oak::tree;
-use oak::filters::{FilterAggregate, filter_hidden_files, filter_non_dirs, GitignoreFilter, GlobFilter};
+use oak::html_processor::HtmlProcessorBuilder;
+use oak::xml_processor::XmlProcessorBuilder;
+use oak::markdown_processor::MarkdownProcessorBuilder;
+use oak::dot_processor::DotProcessorBuilder;
+use oak::list_processor::ListProcessorBuilder;
+use oak::csv_processor::{self, CsvProcessorBuilder, Field};
+use oak::json_processor::JsonProcessorBuilder;
+use oak::json_replay::JsonTree;
+use oak::archive::{self, ArchiveTree};
+use oak::duplicates_processor::DuplicatesProcessorBuilder;
+use oak::git_status::GitStatus;
+use oak::diff::{self, DiffPrinter};
+use oak::hash::Algorithm;
+use oak::snapshot::{self, Snapshot};
+use oak::metadata::Metadata;
+use oak::source::{FsSource, TreeSource};
+use oak::tree::{self, EventSource};
+use oak::watch::{ChangeKind, Recorder, Watcher};
+use oak::browser::{Browser, FilterToggles};
+use oak::columns::{self, Columns, DEFAULT_TIME_FORMAT};
+use oak::filters::{FileFilter, FilterAggregate, Verdict, filter_hidden_files, filter_non_dirs, GitignoreFilter, GlobFilter};
 
 fn die(message: &Display) -> ! {
     writeln!(&mut stderr(), "error: {}", message).expect("Failed to write to stderr");
     process::exit(1);
 }
 
+fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Print whether `path` passes `filters`, and if not, why.
+///
+/// The walk doesn't descend into excluded directories and marks everything in an ignored one as
+/// ignored, so the ancestors of `path` below `root` are checked first, from the top down. Entries
+/// are looked up in `source`, so members of archives and replayed trees are explained as well.
+fn explain(filters: &FilterAggregate, source: &TreeSource, root: &Path, path: &Path) {
+    let (mut ancestor, relative) = match path.strip_prefix(root) {
+        Ok(relative) => (root.to_path_buf(), relative),
+        Err(_) => (PathBuf::new(), path),
+    };
+    let mut ignored_by = None;
+
+    for component in relative.components() {
+        ancestor.push(component.as_os_str());
+
+        if ancestor.as_path() == path {
+            break;
+        } else if let Component::Normal(_) = component {
+            let metadata = source.metadata(&ancestor).unwrap_or_else(|err| die(&err));
+
+            match filters.verdict(&ancestor, &metadata) {
+                Ok(Verdict::Include) => (),
+                Ok(Verdict::Annotate) => {
+                    if ignored_by.is_none() {
+                        let reason = explain_reason(filters, &ancestor, &metadata);
+                        ignored_by = Some((ancestor.clone(), reason));
+                    }
+                },
+                Ok(Verdict::Exclude) => {
+                    println!("{}: excluded, as {} is excluded by {}",
+                             path.display(),
+                             ancestor.display(),
+                             explain_reason(filters, &ancestor, &metadata));
+                    return;
+                },
+                Err(err) => die(&err),
+            }
+        }
+    }
+
+    let metadata = source.metadata(path).unwrap_or_else(|err| die(&err));
+
+    match filters.verdict(path, &metadata) {
+        Ok(Verdict::Exclude) => {
+            println!("{}: excluded by {}", path.display(), explain_reason(filters, path, &metadata))
+        },
+        Ok(Verdict::Annotate) => {
+            println!("{}: marked as ignored by {}",
+                     path.display(),
+                     explain_reason(filters, path, &metadata))
+        },
+        Ok(Verdict::Include) => match ignored_by {
+            Some((ancestor, reason)) => {
+                println!("{}: marked as ignored, as {} is marked as ignored by {}",
+                         path.display(),
+                         ancestor.display(),
+                         reason)
+            },
+            None => println!("{}: included", path.display()),
+        },
+        Err(err) => die(&err),
+    }
+}
+
+/// The name of the filter of `filters` that rejects `path`, and why.
+fn explain_reason(filters: &FilterAggregate, path: &Path, metadata: &Metadata) -> String {
+    filters.explain(path, metadata).unwrap_or_else(|| From::from("unknown filter"))
+}
+
+/// Build the filters selected on the command line, for the tree rooted at `dir`.
+///
+/// Gitignore rules are only applied if `use_git` is set.
+fn build_filters(argv_matches: &clap::ArgMatches, dir: &Path, use_git: bool) -> FilterAggregate {
+    let toggles = FilterToggles {
+        show_hidden: argv_matches.is_present("show-hidden"),
+        gitignore: use_git && !argv_matches.is_present("no-git-ignore"),
+    };
+
+    build_toggled_filters(argv_matches, dir, &toggles)
+}
+
+/// Like `build_filters()`, but with hidden files and gitignore rules set by `toggles` rather than
+/// the command line.
+fn build_toggled_filters(argv_matches: &clap::ArgMatches,
+                         dir: &Path,
+                         toggles: &FilterToggles)
+                         -> FilterAggregate {
+    let mut filters = FilterAggregate::default();
+    let show_ignored = argv_matches.is_present("show-ignored");
+
+    if !toggles.show_hidden {
+        if show_ignored {
+            filters.push_annotating("hidden", filter_hidden_files);
+        } else {
+            filters.push_named("hidden", filter_hidden_files);
+        }
+    }
+
+    if argv_matches.is_present("only-dirs") {
+        filters.push_named("only directories", filter_non_dirs);
+    }
+
+    if toggles.gitignore {
+        match GitignoreFilter::new(dir) {
+            Some(Ok(filter)) => {
+                if show_ignored {
+                    filters.push_annotating("gitignore", filter);
+                } else {
+                    filters.push_named("gitignore", filter);
+                }
+            },
+            Some(Err(err)) => {
+                die(&err);
+            },
+            None => {},
+        }
+    }
+
+    // This is a mess!!
+    if let Some(patterns) = argv_matches.values_of("glob-include") {
+        let result = GlobFilter::from(patterns.map(From::from), false);
+
+        match result {
+            Ok(filter) => filters.push_named("include glob", filter),
+            Err(err) => die(&err),
+        };
+    } else if let Some(patterns) = argv_matches.values_of("glob-exclude") {
+        let result = GlobFilter::from(patterns.map(From::from), true);
+
+        match result {
+            Ok(filter) => filters.push_named("exclude glob", filter),
+            Err(err) => die(&err),
+        };
+    }
+
+    filters
+}
+
+/// Print the difference between the trees rooted at `old` and `new`.
+fn print_diff(argv_matches: &clap::ArgMatches, old: &Path, new: &Path) {
+    let collect = |dir: &Path| {
+        let filters = build_filters(argv_matches, dir, true);
+        let mut tree_iter = tree::TreeIter::new(dir, filters)
+            .unwrap_or_else(|err| die(&format!("Failed to read '{}': {}", dir.display(), err)));
+        tree_iter.one_file_system(argv_matches.is_present("one-file-system"));
+        diff::Node::collect(&mut tree_iter, dir.to_path_buf()).unwrap_or_else(|err| die(&err))
+    };
+
+    let compare_content = if argv_matches.is_present("compare-content") {
+        Some(hash_algorithm(argv_matches).unwrap_or(Algorithm::Sha256))
+    } else {
+        None
+    };
+
+    let old_tree = collect(old);
+    let new_tree = collect(new);
+    let entries = diff::diff(&old_tree, &new_tree, compare_content)
+        .unwrap_or_else(|err| die(&err));
+
+    let mut printer = DiffPrinter::new();
+    printer.colored(stdout_is_tty());
+    printer.print(old, new, &entries);
+}
+
+/// The algorithm given with --hash, if any.
+fn hash_algorithm(argv_matches: &clap::ArgMatches) -> Option<Algorithm> {
+    argv_matches.value_of("hash").and_then(Algorithm::from_name)
+}
+
+/// Number of levels of archives to expand with --expand-archives, or zero.
+fn archive_depth(argv_matches: &clap::ArgMatches) -> usize {
+    if !argv_matches.is_present("expand-archives") {
+        0
+    } else if argv_matches.value_of("expand-archives").is_some() {
+        value_t!(argv_matches, "expand-archives", usize).unwrap_or_else(|err| err.exit())
+    } else {
+        1
+    }
+}
+
+/// How long to wait for more changes before re-rendering, in watch mode.
+const WATCH_DEBOUNCE_MS: u64 = 200;
+
+/// Number of removals and renames listed below the tree in watch mode.
+const WATCH_LISTED_CHANGES: usize = 10;
+
+/// Render the tree rooted at `dir` with `procor`, and render it again whenever entries are
+/// created, removed or renamed. The entries created or renamed since the previous render are
+/// highlighted, and removals and renames are listed below the tree.
+///
+/// Changes to entries that the filters leave out don't cause a new render. Unreadable directories
+/// are marked in the tree, and other errors while walking are shown below it until the next
+/// render.
+fn watch(argv_matches: &clap::ArgMatches,
+         dir: &Path,
+         procor: &mut PrintProcessorBuilder,
+         file_limit: Option<usize>,
+         max_children: Option<usize>,
+         algorithm: Option<Algorithm>)
+         -> ! {
+    let mut watcher = Watcher::new()
+        .unwrap_or_else(|err| die(&format!("Failed to watch '{}': {}", dir.display(), err)));
+    let mut status = format!("Watching {}", dir.display());
+
+    loop {
+        // Rebuild the filters every time, as .gitignore files may have changed as well
+        let filters = build_filters(argv_matches, dir, true);
+        procor.filter_stats(filters.stats());
+
+        let mut tree_iter = tree::TreeIter::new(dir, filters).unwrap_or_else(|err| die(&err));
+
+        if let Some(limit) = file_limit {
+            tree_iter.file_limit(limit);
+        }
+
+        if let Some(max) = max_children {
+            tree_iter.max_children(max);
+        }
+
+        if let Some(algorithm) = algorithm {
+            tree_iter.hash(algorithm);
+        }
+
+        tree_iter.one_file_system(argv_matches.is_present("one-file-system"));
+
+        // Clear the screen and move to the top left corner
+        print!("\x1b[2J\x1b[H");
+
+        let mut recorder = Recorder::new(&mut tree_iter);
+        let error = procor.build().process(&mut recorder);
+
+        println!("\n{}", status);
+
+        if let Some(err) = error {
+            println!("error: {}", err);
+        }
+
+        for path in Some(dir.to_path_buf()).iter().chain(recorder.dirs()) {
+            match watcher.watch_dir(path) {
+                // Removed since it was walked, which is a change that renders the tree again
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => die(&format!("Failed to watch '{}': {}", path.display(), err)),
+                Ok(()) => {},
+            }
+        }
+
+        let known = recorder.into_paths();
+        let filters = build_filters(argv_matches, dir, true);
+        let mut created = HashSet::new();
+        let mut removed = Vec::new();
+        let mut renamed = Vec::new();
+
+        // Wait for changes that affect the rendered tree
+        while created.is_empty() && removed.is_empty() && renamed.is_empty() {
+            let changes = watcher.wait(Duration::from_millis(WATCH_DEBOUNCE_MS))
+                .unwrap_or_else(|err| die(&format!("Failed to watch '{}': {}", dir.display(), err)));
+            // The sources of renames by cookie, until their destination is seen, along with
+            // whether they were created since the render
+            let mut renamed_from = HashMap::new();
+
+            for change in changes {
+                match change.kind {
+                    ChangeKind::Created => {
+                        // An entry that can't be judged is shown, to be on the safe side
+                        let passes = fs::symlink_metadata(&change.path)
+                            .map(|metadata| filters.verdict(&change.path, &Metadata::from(metadata)))
+                            .map(|verdict| verdict.map(|verdict| verdict != Verdict::Exclude))
+                            .unwrap_or(Ok(false))
+                            .unwrap_or(true);
+
+                        match renamed_from.remove(&change.cookie) {
+                            Some((from, false)) => {
+                                if passes {
+                                    renamed.push((from, change.path));
+                                } else {
+                                    removed.push(from);
+                                }
+                            },
+                            _ => {
+                                if passes {
+                                    created.insert(change.path);
+                                }
+                            },
+                        }
+                    },
+                    ChangeKind::Removed => {
+                        let was_created = created.remove(&change.path);
+
+                        if !was_created && !known.contains(&change.path) {
+                            continue;
+                        }
+
+                        if change.cookie != 0 {
+                            renamed_from.insert(change.cookie, (change.path, was_created));
+                        } else if !was_created {
+                            removed.push(change.path);
+                        }
+                    },
+                }
+            }
+
+            // Renamed out of the watched directories
+            for (_, (from, was_created)) in renamed_from {
+                if !was_created {
+                    removed.push(from);
+                }
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        let listed: Vec<_> = removed.iter()
+            .map(|path| format!("removed {}", path.display()))
+            .chain(renamed.iter().map(|&(ref from, ref to)| {
+                format!("renamed {} -> {}", from.display(), to.display())
+            }))
+            .collect();
+
+        status = format!("Last change at {}: {} created, {} removed, {} renamed",
+                         columns::format_time(now, "%H:%M:%S"),
+                         created.len(),
+                         removed.len(),
+                         renamed.len());
+
+        for line in listed.iter().take(WATCH_LISTED_CHANGES) {
+            status.push_str("\n  ");
+            status.push_str(line);
+        }
+
+        if listed.len() > WATCH_LISTED_CHANGES {
+            status.push_str(&format!("\n  … {} more", listed.len() - WATCH_LISTED_CHANGES));
+        }
+
+        created.extend(renamed.into_iter().map(|(_, to)| to));
+        procor.highlight(created);
+    }
+}
+
 fn main() {
     let argv_matches = clap::App::new("Oak")
         .version(crate_version!())
         .author(crate_authors!())
         .about("A recursive directory listing utility for the modern age.")
         .arg(clap::Arg::with_name("DIR")
-            .help("The directory to list, defaults to cwd")
+            .help("The directory to list, defaults to cwd. Tar archives, optionally compressed \
+                   with gzip or zstd, and zip archives are listed like directories")
             .index(1))
+        .arg(clap::Arg::with_name("expand-archives")
+            .help("List the members of archives found in the tree, with dashed lines, and of \
+                   archives within those up to DEPTH levels deep, defaulting to 1")
+            .long("expand-archives")
+            .value_name("DEPTH")
+            .min_values(0)
+            .require_equals(true)
+            .takes_value(true))
+        .arg(clap::Arg::with_name("one-file-system")
+            .help("Stay on the file system of DIR, listing mount points without descending into \
+                   them")
+            .short("x")
+            .long("one-file-system"))
+        .arg(clap::Arg::with_name("from-json")
+            .help("Read the tree from a JSON listing made with --format json, instead of DIR")
+            .long("from-json")
+            .value_name("FILE")
+            .conflicts_with("DIR")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("diff")
+            .help("Show the differences between the trees rooted at A and B")
+            .long("diff")
+            .value_names(&["A", "B"])
+            .number_of_values(2)
+            .conflicts_with_all(&["DIR", "from-json"])
+            .takes_value(true))
+        .arg(clap::Arg::with_name("compare-content")
+            .help("Compare files by content hash rather than mtime, with --diff. Uses the \
+                   algorithm given with --hash, or sha256")
+            .long("compare-content"))
+        .arg(clap::Arg::with_name("save-snapshot")
+            .help("Save a snapshot of the tree to FILE, for later use with --against")
+            .long("save-snapshot")
+            .value_name("FILE")
+            .conflicts_with_all(&["diff", "against"])
+            .takes_value(true))
+        .arg(clap::Arg::with_name("against")
+            .help("Show the tree annotated with the changes since the snapshot in FILE")
+            .long("against")
+            .value_name("FILE")
+            .conflicts_with("diff")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("hash")
+            .help("Hash the contents of files with ALGORITHM, printing the digests along with \
+                   a digest of each directory in tree, JSON and CSV output, and recording them \
+                   in snapshots")
+            .long("hash")
+            .value_name("ALGORITHM")
+            .possible_values(&["sha256", "blake3", "xxh3"])
+            .takes_value(true))
+        .arg(clap::Arg::with_name("watch")
+            .help("Keep watching the tree, rendering it again whenever entries are created, \
+                   removed or renamed, with new entries highlighted")
+            .long("watch")
+            .conflicts_with_all(&["from-json", "diff", "save-snapshot", "against", "format",
+                                  "xml", "list", "duplicates", "explain", "expand-archives"]))
+        .arg(clap::Arg::with_name("interactive")
+            .help("Browse the tree interactively, and print the path selected with enter, e.g. \
+                   for cd $(oak -i)")
+            .short("i")
+            .long("interactive")
+            .conflicts_with_all(&["from-json", "diff", "save-snapshot", "against", "format",
+                                  "xml", "list", "duplicates", "explain", "watch",
+                                  "expand-archives"]))
+        .arg(clap::Arg::with_name("format")
+            .help("Select the output format")
+            .long("format")
+            .value_name("FORMAT")
+            .possible_values(&["tree", "html", "xml", "markdown", "dot", "csv", "tsv", "json"])
+            .takes_value(true))
+        .arg(clap::Arg::with_name("xml")
+            .help("Output XML, same as --format xml")
+            .short("X")
+            .conflicts_with("format"))
+        .arg(clap::Arg::with_name("list")
+            .help("List one path per line instead of drawing a tree")
+            .long("list")
+            .conflicts_with_all(&["format", "xml"]))
+        .arg(clap::Arg::with_name("duplicates")
+            .help("Print groups of files with identical contents instead of drawing a tree. \
+                   Contents are hashed with the algorithm given with --hash, or sha256")
+            .long("duplicates")
+            .conflicts_with_all(&["format", "xml", "list"]))
+        .arg(clap::Arg::with_name("null")
+            .help("Terminate paths with NUL instead of newline, in list output")
+            .short("0")
+            .long("null"))
+        .arg(clap::Arg::with_name("base-href")
+            .help("Link files to URL followed by their relative path, in HTML and Markdown output")
+            .long("base-href")
+            .value_name("URL")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("links")
+            .help("Link files to their relative path, in Markdown output (implied by --base-href)")
+            .long("links"))
+        .arg(clap::Arg::with_name("code")
+            .help("Format names as code, in Markdown output")
+            .long("code"))
+        .arg(clap::Arg::with_name("size-weighted")
+            .help("Scale file nodes by size, in DOT output")
+            .long("size-weighted"))
+        .arg(clap::Arg::with_name("fields")
+            .help("Comma separated fields of CSV and TSV output, out of path, type, size, mtime, \
+                   mode, owner, depth, git_status and digest. The digest field is included by \
+                   default with --hash")
+            .long("fields")
+            .value_name("FIELDS")
+            .use_delimiter(true)
+            .takes_value(true))
         .arg(clap::Arg::with_name("show-hidden")
              .help("Show hidden files")
              .short("a"))
+        .arg(clap::Arg::with_name("show-ignored")
+             .help("Show hidden and gitignored files, marked as ignored")
+             .long("show-ignored"))
         .arg(clap::Arg::with_name("only-dirs")
              .help("List directories only")
              .short("d"))
@@ -47,52 +528,375 @@ fn main() {
             .multiple(true)
             .number_of_values(1)
             .takes_value(true))
+        .arg(clap::Arg::with_name("compact")
+            .help("Collapse chains of single-child directories into one line")
+            .long("compact"))
+        .arg(clap::Arg::with_name("permissions")
+            .help("Show file type and permissions, e.g. drwxr-xr-x")
+            .short("p"))
+        .arg(clap::Arg::with_name("owner")
+            .help("Show the name of the owning user")
+            .short("u"))
+        .arg(clap::Arg::with_name("group")
+            .help("Show the name of the owning group (-G, as -g is --no-git)")
+            .long("group")
+            .short("G"))
+        .arg(clap::Arg::with_name("date")
+            .help("Show the date of last modification")
+            .short("D"))
+        .arg(clap::Arg::with_name("timefmt")
+            .help("Format dates with a strftime style FORMAT, implies -D")
+            .long("timefmt")
+            .value_name("FORMAT")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("inodes")
+            .help("Show the inode number")
+            .long("inodes"))
+        .arg(clap::Arg::with_name("device")
+            .help("Show the device id")
+            .long("device"))
+        .arg(clap::Arg::with_name("classify")
+            .help("Append a file type indicator (one of /*@|=) to entries")
+            .short("F")
+            .long("type"))
+        .arg(clap::Arg::with_name("report")
+            .help("Select the summary printed after the tree")
+            .long("report")
+            .value_name("FORMAT")
+            .possible_values(&["default", "full"])
+            .takes_value(true))
+        .arg(clap::Arg::with_name("explain")
+            .help("Explain whether PATH is listed, and which filter excludes it if not")
+            .long("explain")
+            .value_name("PATH")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("filelimit")
+            .help("Do not descend into directories with more than N entries")
+            .long("filelimit")
+            .value_name("N")
+            .takes_value(true))
+        .arg(clap::Arg::with_name("max-children")
+            .help("List at most N entries per directory")
+            .long("max-children")
+            .value_name("N")
+            .takes_value(true))
         .get_matches();
 
-    let dir = Path::new(argv_matches.value_of("DIR").unwrap_or("."));
-    let mut filters = FilterAggregate::default();
+    if let Some(mut roots) = argv_matches.values_of("diff") {
+        let old = roots.next().unwrap_or(".");
+        let new = roots.next().unwrap_or(".");
+        print_diff(&argv_matches, Path::new(old), Path::new(new));
+        return;
+    }
+
+    let json_tree = argv_matches.value_of("from-json").map(|path| {
+        File::open(path)
+            .map_err(From::from)
+            .and_then(JsonTree::from_reader)
+            .unwrap_or_else(|err| die(&format!("Failed to read '{}': {}", path, err)))
+    });
+
+    let archive_depth = archive_depth(&argv_matches);
+
+    let archive_tree = argv_matches.value_of("DIR").map(Path::new).and_then(|path| {
+        if !path.is_file() {
+            return None;
+        }
+
+        let format = archive::Format::from_path(path).unwrap_or_else(|| {
+            die(&format!("'{}' is neither a directory nor a supported archive", path.display()))
+        });
+
+        Some(ArchiveTree::open(path, format, archive_depth)
+            .unwrap_or_else(|err| die(&format!("Failed to read '{}': {}", path.display(), err))))
+    });
+
+    let root = match json_tree {
+        Some(ref json_tree) => json_tree.root().to_path_buf(),
+        None => PathBuf::from(argv_matches.value_of("DIR").unwrap_or(".")),
+    };
+    let dir = root.as_path();
+    // Trees that aren't on disk have no git repository
+    let replaying = json_tree.is_some() || archive_tree.is_some();
     let mut procor = PrintProcessorBuilder::new(From::from(dir));
 
-    if !argv_matches.is_present("show-hidden") {
-        filters.push(filter_hidden_files);
+    let show_ignored = argv_matches.is_present("show-ignored");
+
+    if show_ignored {
+        // Only dim when writing to a terminal, so piped output keeps the tags
+        procor.dim_ignored(stdout_is_tty());
     }
 
     if argv_matches.is_present("only-dirs") {
-        filters.push(filter_non_dirs);
         procor.summary(SummaryFormat::DirCount);
     }
 
-    if !argv_matches.is_present("no-git-ignore") {
-        match GitignoreFilter::new(dir) {
-            Some(Ok(filter)) => {
-                filters.push(filter);
-            },
-            Some(Err(err)) => {
-                die(&err);
-            },
-            None => {},
-        }
+    if argv_matches.value_of("report") == Some("full") {
+        procor.summary(SummaryFormat::Full);
     }
 
-    // This is a mess!!
-    if let Some(patterns) = argv_matches.values_of("glob-include") {
-        let result = GlobFilter::from(patterns.map(From::from), false);
+    if argv_matches.is_present("compact") {
+        procor.compact(true);
+    }
 
-        match result {
-            Ok(filter) => filters.push(filter),
-            Err(err) => die(&err),
+    let mut columns = Columns::default();
+    columns.permissions(argv_matches.is_present("permissions"))
+        .owner(argv_matches.is_present("owner"))
+        .group(argv_matches.is_present("group"))
+        .inode(argv_matches.is_present("inodes"))
+        .device(argv_matches.is_present("device"));
+
+    if let Some(format) = argv_matches.value_of("timefmt") {
+        columns.date(From::from(format));
+    } else if argv_matches.is_present("date") {
+        columns.date(From::from(DEFAULT_TIME_FORMAT));
+    }
+
+    procor.columns(columns);
+
+    if argv_matches.is_present("classify") {
+        procor.classify(true);
+    }
+
+    let algorithm = hash_algorithm(&argv_matches);
+
+    if let Some(algorithm) = algorithm {
+        procor.digests(algorithm);
+    }
+
+    // A replayed tree isn't on disk for git to look at
+    let filters = build_filters(&argv_matches, dir, !replaying);
+
+    if let Some(path) = argv_matches.value_of("explain") {
+        let source: Box<TreeSource> = match (json_tree, archive_tree) {
+            (Some(json_tree), _) => Box::new(json_tree.into_source()),
+            (None, Some(archive_tree)) => Box::new(archive_tree.into_source()),
+            (None, None) => Box::new(FsSource),
         };
-    } else if let Some(patterns) = argv_matches.values_of("glob-exclude") {
-        let result = GlobFilter::from(patterns.map(From::from), true);
 
-        match result {
-            Ok(filter) => filters.push(filter),
-            Err(err) => die(&err),
+        explain(&filters, &*source, dir, Path::new(path));
+        return;
+    }
+
+    if argv_matches.is_present("interactive") {
+        let toggles = FilterToggles {
+            show_hidden: argv_matches.is_present("show-hidden"),
+            gitignore: !argv_matches.is_present("no-git-ignore"),
+        };
+        let make_filters = |toggles: &FilterToggles| {
+            build_toggled_filters(&argv_matches, dir, toggles)
         };
+
+        let mut browser = Browser::new(dir, toggles, make_filters);
+        browser.one_file_system(argv_matches.is_present("one-file-system"));
+        let selected = browser.run().unwrap_or_else(|err| die(&err));
+
+        match selected {
+            Some(path) => println!("{}", path.display()),
+            // Leave nothing for e.g. cd to act on
+            None => process::exit(1),
+        }
+
+        return;
+    }
+
+    procor.filter_stats(filters.stats());
+
+    let file_limit = if argv_matches.is_present("filelimit") {
+        Some(value_t!(argv_matches, "filelimit", usize).unwrap_or_else(|err| err.exit()))
+    } else {
+        None
+    };
+
+    let max_children = if argv_matches.is_present("max-children") {
+        Some(value_t!(argv_matches, "max-children", usize).unwrap_or_else(|err| err.exit()))
+    } else {
+        None
+    };
+
+    if argv_matches.is_present("watch") {
+        watch(&argv_matches, dir, &mut procor, file_limit, max_children, algorithm);
     }
 
-    let mut tree_iter = tree::TreeIter::new(dir, filters).unwrap_or_else(|err| die(&err));
-    if let Some(err) = procor.build().process(&mut tree_iter) {
+    let tree_iter = match (json_tree, archive_tree) {
+        (Some(json_tree), _) => tree::TreeIter::with_source(json_tree.into_source(), dir, filters),
+        (None, Some(archive_tree)) => {
+            tree::TreeIter::with_source(archive_tree.into_source(), dir, filters)
+        },
+        (None, None) => tree::TreeIter::new(dir, filters),
+    };
+    let mut tree_iter = tree_iter.unwrap_or_else(|err| die(&err));
+
+    if let Some(limit) = file_limit {
+        tree_iter.file_limit(limit);
+    }
+
+    if let Some(max) = max_children {
+        tree_iter.max_children(max);
+    }
+
+    if let Some(algorithm) = algorithm {
+        tree_iter.hash(algorithm);
+    }
+
+    if !replaying {
+        tree_iter.one_file_system(argv_matches.is_present("one-file-system"));
+
+        if archive_depth > 0 {
+            // The archives on disk are the first level
+            tree_iter.expand_archives(archive_depth - 1);
+        }
+    }
+
+    let mut events: Box<EventSource> = Box::new(tree_iter);
+
+    if let Some(path) = argv_matches.value_of("save-snapshot") {
+        let file = File::create(path)
+            .unwrap_or_else(|err| die(&format!("Failed to create '{}': {}", path, err)));
+        snapshot::save(&mut *events, dir, BufWriter::new(file), algorithm)
+            .unwrap_or_else(|err| die(&err));
+        return;
+    }
+
+    if let Some(path) = argv_matches.value_of("against") {
+        let snapshot = File::open(path)
+            .map_err(From::from)
+            .and_then(|file| Snapshot::load(BufReader::new(file), dir))
+            .unwrap_or_else(|err| die(&format!("Failed to read '{}': {}", path, err)));
+        let current = diff::Node::collect(&mut *events, dir.to_path_buf())
+            .unwrap_or_else(|err| die(&err));
+        let entries = diff::diff(snapshot.tree(), &current, snapshot.algorithm())
+            .unwrap_or_else(|err| die(&err));
+
+        let mut printer = DiffPrinter::new();
+        printer.colored(stdout_is_tty()).collapse_unchanged(false);
+        printer.print(Path::new(path), dir, &entries);
+        return;
+    }
+
+    let format = if argv_matches.is_present("xml") {
+        Some("xml")
+    } else if argv_matches.is_present("list") {
+        Some("list")
+    } else if argv_matches.is_present("duplicates") {
+        Some("duplicates")
+    } else {
+        argv_matches.value_of("format")
+    };
+
+    let mut processor: Box<TreeProcessor> = match format {
+        Some("html") => {
+            let mut builder = HtmlProcessorBuilder::new(From::from(dir));
+
+            if let Some(base_href) = argv_matches.value_of("base-href") {
+                builder.base_href(From::from(base_href));
+            }
+
+            if let Some(format) = argv_matches.value_of("timefmt") {
+                builder.time_format(From::from(format));
+            }
+
+            Box::new(builder.build())
+        },
+        Some("xml") => {
+            let mut builder = XmlProcessorBuilder::new(From::from(dir));
+
+            if let Some(format) = argv_matches.value_of("timefmt") {
+                builder.time_format(From::from(format));
+            }
+
+            Box::new(builder.build())
+        },
+        Some("markdown") => {
+            let mut builder = MarkdownProcessorBuilder::new(From::from(dir));
+            builder.code(argv_matches.is_present("code"));
+
+            // A base URL is only of use for links, so it implies them
+            if argv_matches.is_present("links") || argv_matches.is_present("base-href") {
+                builder.links(From::from(argv_matches.value_of("base-href").unwrap_or("")));
+            }
+
+            Box::new(builder.build())
+        },
+        Some("json") => {
+            let mut builder = JsonProcessorBuilder::new(From::from(dir));
+
+            if let Some(algorithm) = algorithm {
+                builder.digests(algorithm);
+            }
+
+            Box::new(builder.build())
+        },
+        Some("dot") => {
+            let mut builder = DotProcessorBuilder::new(From::from(dir));
+            builder.size_weighted(argv_matches.is_present("size-weighted"));
+            Box::new(builder.build())
+        },
+        Some("duplicates") => {
+            let mut builder = DuplicatesProcessorBuilder::new();
+
+            if let Some(algorithm) = algorithm {
+                builder.algorithm(algorithm);
+            }
+
+            Box::new(builder.build())
+        },
+        Some("list") => {
+            let mut builder = ListProcessorBuilder::new();
+            builder.null_terminated(argv_matches.is_present("null"))
+                .classify(argv_matches.is_present("classify"));
+            Box::new(builder.build())
+        },
+        Some(format @ "csv") | Some(format @ "tsv") => {
+            let format = if format == "csv" {
+                csv_processor::Format::Csv
+            } else {
+                csv_processor::Format::Tsv
+            };
+            let mut builder = CsvProcessorBuilder::new(format);
+
+            let fields = match argv_matches.values_of("fields") {
+                Some(names) => {
+                    names.map(|name| {
+                            Field::from_name(name)
+                                .unwrap_or_else(|| die(&format!("Unknown field '{}'", name)))
+                        })
+                        .collect()
+                },
+                None => {
+                    let mut fields = csv_processor::DEFAULT_FIELDS.to_vec();
+
+                    if algorithm.is_some() {
+                        fields.push(Field::Digest);
+                    }
+
+                    fields
+                },
+            };
+
+            if let Some(algorithm) = algorithm {
+                builder.digests(algorithm);
+            }
+
+            if fields.contains(&Field::GitStatus) && !replaying {
+                match GitStatus::new(dir) {
+                    Some(Ok(git_status)) => {
+                        builder.git_status(git_status);
+                    },
+                    Some(Err(err)) => die(&err),
+                    None => {},
+                }
+            }
+
+            builder.fields(fields);
+            Box::new(builder.build())
+        },
+        _ => Box::new(procor.build()),
+    };
+
+    if let Some(err) = processor.process(&mut *events) {
+        // Dropping the processor flushes whatever it buffered before we exit
+        drop(processor);
         die(&err);
     }
 }
@@ -0,0 +1,161 @@
+//! `MarkdownProcessor` and supporting types.
+//!
+//! The tree is rendered as nested bullet lists, which display properly on GitHub, GitLab and
+//! other CommonMark based renderers.
+
+use std::path::PathBuf;
+use super::tree_processor::TreeProcessor;
+use super::tree::Entry;
+use super::html_processor::url_encode;
+
+/// Builder for `MarkdownProcessor`.
+pub struct MarkdownProcessorBuilder {
+    root: PathBuf,
+    code: bool,
+    link_prefix: Option<String>,
+}
+
+impl MarkdownProcessorBuilder {
+    /// Create a new builder.
+    pub fn new(root: PathBuf) -> Self {
+        MarkdownProcessorBuilder {
+            root: root,
+            code: false,
+            link_prefix: None,
+        }
+    }
+
+    /// Format names as inline code.
+    pub fn code(&mut self, code: bool) -> &mut Self {
+        self.code = code;
+        self
+    }
+
+    /// Link each file to its path relative to the root, prepended with `prefix`.
+    ///
+    /// Use an empty prefix for links relative to the document.
+    pub fn links(&mut self, prefix: String) -> &mut Self {
+        self.link_prefix = Some(prefix);
+        self
+    }
+
+    /// Build a `MarkdownProcessor`.
+    pub fn build(&self) -> MarkdownProcessor {
+        MarkdownProcessor {
+            root: self.root.clone(),
+            code: self.code,
+            link_prefix: self.link_prefix.clone(),
+            depth: 0,
+        }
+    }
+}
+
+/// A `TreeProcessor` printing the tree as a Markdown list.
+///
+/// # Example
+///
+/// ```text
+/// - a
+/// - b/
+///   - 1
+///   - 2
+/// - c/
+/// - d
+/// ```
+pub struct MarkdownProcessor {
+    root: PathBuf,
+    code: bool,
+    link_prefix: Option<String>,
+    depth: usize,
+}
+
+impl MarkdownProcessor {
+    fn print_item(&self, text: &str) {
+        println!("{}- {}", "  ".repeat(self.depth), text);
+    }
+
+    /// The formatted name of `entry`, with a trailing slash for directories.
+    fn name(&self, entry: &Entry) -> String {
+        let mut name = entry.file_name().into_owned();
+
+        if entry.metadata().is_dir() {
+            name.push('/');
+        }
+
+        if self.code {
+            code_span(&name)
+        } else {
+            escape(&name)
+        }
+    }
+}
+
+impl TreeProcessor for MarkdownProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        let name = self.name(entry);
+        self.print_item(&name);
+        self.depth += 1;
+    }
+
+    fn close_dir(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        let name = self.name(entry);
+
+        match self.link_prefix {
+            Some(ref prefix) => {
+                let relative = entry.path().strip_prefix(&self.root).unwrap_or(entry.path());
+                self.print_item(&format!("[{}]({}{})", name, prefix, url_encode(relative)));
+            },
+            None => self.print_item(&name),
+        }
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        let name = self.name(entry);
+        self.print_item(&format!("{} ({} entries exceeds filelimit)", name, num_entries));
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        self.print_item(&format!("… {} more", num_entries));
+    }
+}
+
+/// Escape the characters that could be interpreted as Markdown syntax.
+///
+/// Inline syntax is escaped anywhere, while block syntax such as `#` headings or `1.` list
+/// markers is only escaped at the start of `text`.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut leading_digits = true;
+
+    for (i, c) in text.chars().enumerate() {
+        let is_block_syntax = (i == 0 && "#+-".contains(c)) ||
+                              (i > 0 && leading_digits && (c == '.' || c == ')'));
+
+        if "\\`*_[]<>|~".contains(c) || is_block_syntax {
+            escaped.push('\\');
+        }
+
+        leading_digits = leading_digits && c.is_ascii_digit();
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Format `text` as an inline code span, using a delimiter longer than any run of backticks in
+/// `text`.
+pub fn code_span(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let delimiter = "`".repeat(longest_run + 1);
+
+    // Padding keeps backticks at the edges from merging with the delimiter
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", delimiter, text, delimiter)
+    } else {
+        format!("{}{}{}", delimiter, text, delimiter)
+    }
+}
@@ -0,0 +1,194 @@
+//! An owned metadata type, independent of the file system.
+//!
+//! `Metadata` carries the subset of `fs::Metadata` used by oak, so that entries can also be
+//! created from sources such as saved listings.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+/// The type of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+/// All file types.
+const ALL_FILE_TYPES: [FileType; 7] = [FileType::File,
+                                       FileType::Dir,
+                                       FileType::Symlink,
+                                       FileType::Fifo,
+                                       FileType::Socket,
+                                       FileType::BlockDevice,
+                                       FileType::CharDevice];
+
+impl FileType {
+    pub fn is_file(&self) -> bool {
+        *self == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        *self == FileType::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        *self == FileType::Symlink
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        *self == FileType::Fifo
+    }
+
+    pub fn is_socket(&self) -> bool {
+        *self == FileType::Socket
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        *self == FileType::BlockDevice
+    }
+
+    pub fn is_char_device(&self) -> bool {
+        *self == FileType::CharDevice
+    }
+
+    /// A lowercase name for the file type, e.g. `directory` or `block_device`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FileType::File => "file",
+            FileType::Dir => "directory",
+            FileType::Symlink => "symlink",
+            FileType::Fifo => "fifo",
+            FileType::Socket => "socket",
+            FileType::BlockDevice => "block_device",
+            FileType::CharDevice => "char_device",
+        }
+    }
+
+    /// Look up a file type by the name returned from `name()`.
+    pub fn from_name(name: &str) -> Option<FileType> {
+        ALL_FILE_TYPES.iter().cloned().find(|file_type| file_type.name() == name)
+    }
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(file_type: fs::FileType) -> Self {
+        if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_fifo() {
+            FileType::Fifo
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_char_device() {
+            FileType::CharDevice
+        } else {
+            FileType::File
+        }
+    }
+}
+
+/// Metadata about a file, mirroring the accessors of `fs::Metadata` and `MetadataExt`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The type of the file.
+    pub file_type: FileType,
+    /// Size in bytes.
+    pub len: u64,
+    /// Permission bits, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Owning user id.
+    pub uid: u32,
+    /// Owning group id.
+    pub gid: u32,
+    /// Inode number.
+    pub ino: u64,
+    /// Id of the device containing the file.
+    pub dev: u64,
+}
+
+impl Metadata {
+    /// Create metadata for a file of `file_type` with all other fields zeroed.
+    pub fn new(file_type: FileType) -> Self {
+        Metadata {
+            file_type: file_type,
+            len: 0,
+            mode: 0,
+            mtime: 0,
+            uid: 0,
+            gid: 0,
+            ino: 0,
+            dev: 0,
+        }
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type.is_file()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn mtime(&self) -> i64 {
+        self.mtime
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+}
+
+impl<'a> From<&'a fs::Metadata> for Metadata {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Metadata {
+            file_type: From::from(metadata.file_type()),
+            len: metadata.len(),
+            mode: metadata.mode() & 0o7777,
+            mtime: metadata.mtime(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            ino: metadata.ino(),
+            dev: metadata.dev(),
+        }
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        From::from(&metadata)
+    }
+}
@@ -3,11 +3,16 @@
 //! This processor is designed to output in a format inspired by the classic `tree` command line
 //! utility.
 
-use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt::Display;
-use std::path::{Path, PathBuf};
+use std::io::{self, Stdout, Write};
+use std::path::PathBuf;
 use super::tree_processor::TreeProcessor;
-use super::tree::Entry;
+use super::tree::{Entry, EventSource, ReadDirError};
+use super::report::ReportCollector;
+use super::filters::FilterStats;
+use super::columns::{self, Columns};
+use super::hash::{self, Algorithm, MerkleStack};
 
 /// A summary format for `PrintProcessor`.
 #[derive(Clone)]
@@ -18,6 +23,10 @@ pub enum SummaryFormat {
     DirCount,
     /// Print both number of directories and files, e.g. "2 directories, 10 files".
     DirAndFileCount,
+    /// Print an extended report with sizes, extensions, largest files and more.
+    ///
+    /// See `report::Report` for the full set of statistics.
+    Full,
 }
 
 /// Builder for `PrintProcessor`.
@@ -27,6 +36,13 @@ pub enum SummaryFormat {
 pub struct PrintProcessorBuilder {
     summary_format: SummaryFormat,
     root: PathBuf,
+    compact: bool,
+    columns: Columns,
+    classify: bool,
+    filter_stats: Option<FilterStats>,
+    dim_ignored: bool,
+    digests: Option<Algorithm>,
+    highlighted: HashSet<PathBuf>,
 }
 
 impl PrintProcessorBuilder {
@@ -35,6 +51,13 @@ impl PrintProcessorBuilder {
         PrintProcessorBuilder {
             summary_format: SummaryFormat::DirAndFileCount,
             root: root,
+            compact: false,
+            columns: Columns::default(),
+            classify: false,
+            filter_stats: None,
+            dim_ignored: false,
+            digests: None,
+            highlighted: HashSet::new(),
         }
     }
 
@@ -44,23 +67,103 @@ impl PrintProcessorBuilder {
         self
     }
 
-    /// Build a `PrintProcessor`.
+    /// Collapse chains of directories that each contain only a single subdirectory into one line,
+    /// e.g. `com/example/app`.
+    pub fn compact(&mut self, compact: bool) -> &mut Self {
+        self.compact = compact;
+        self
+    }
+
+    /// Set the metadata columns printed before each entry.
+    pub fn columns(&mut self, columns: Columns) -> &mut Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Append a file type indicator to each name, like `ls -F`.
+    ///
+    /// See `columns::indicator()` for the indicators used.
+    pub fn classify(&mut self, classify: bool) -> &mut Self {
+        self.classify = classify;
+        self
+    }
+
+    /// Break down the filtered entries per filter in the full summary.
+    pub fn filter_stats(&mut self, stats: FilterStats) -> &mut Self {
+        self.filter_stats = Some(stats);
+        self
+    }
+
+    /// Render ignored entries dimmed using ANSI escape codes, rather than tagged with
+    /// `[ignored]`. See `Entry::is_ignored()`.
+    pub fn dim_ignored(&mut self, dim: bool) -> &mut Self {
+        self.dim_ignored = dim;
+        self
+    }
+
+    /// Print a Merkle-style digest of each directory below its entries, and of the whole tree
+    /// after the summary, combining the digests of the entries with `algorithm`. See
+    /// `hash::MerkleStack`.
+    pub fn digests(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.digests = Some(algorithm);
+        self
+    }
+
+    /// Render the entries at `paths` highlighted using ANSI escape codes, e.g. to point out
+    /// recent changes. Paths must be given as walked, i.e. prefixed with the root.
+    pub fn highlight(&mut self, paths: HashSet<PathBuf>) -> &mut Self {
+        self.highlighted = paths;
+        self
+    }
+
+    /// Build a `PrintProcessor` printing to stdout.
     ///
     /// This method also prints the root, which sets up for subsequent output from the processor.
     pub fn build(&self) -> PrintProcessor {
-        println!("{}", self.root.display());
+        self.build_to(io::stdout())
+    }
 
-        PrintProcessor {
+    /// Build a `PrintProcessor` writing to `writer`, e.g. a `Vec<u8>` to inspect the output.
+    ///
+    /// Like `build()`, this method writes the root right away.
+    pub fn build_to<W: Write>(&self, writer: W) -> PrintProcessor<W> {
+        let mut processor = PrintProcessor {
+            writer: writer,
             dir_has_next: vec![true],
             num_dirs: 0,
             num_files: 0,
             summary_format: self.summary_format.clone(),
-        }
+            report: match self.summary_format {
+                SummaryFormat::Full => {
+                    let mut collector = ReportCollector::new();
+                    if let Some(ref stats) = self.filter_stats {
+                        collector.filter_stats(stats.clone());
+                    }
+                    Some(collector)
+                },
+                _ => None,
+            },
+            compact: self.compact,
+            columns: self.columns.clone(),
+            classify: self.classify,
+            dim_ignored: self.dim_ignored,
+            pending_dir: None,
+            collapsed: Vec::new(),
+            dir_names: Vec::new(),
+            merkle: self.digests.map(MerkleStack::new),
+            root_digest: None,
+            highlighted: self.highlighted.clone(),
+        };
+
+        processor.write_line(&self.root.display());
+        processor
     }
 }
 
 /// A `TreeProcessor` for printing the events in a clasic `tree`-like format.
 ///
+/// Archive members are drawn with dashed lines, see `Entry::is_archive_member()`.
+///
 /// # Example
 /// This is an example of the output of this processor.
 ///
@@ -69,43 +172,138 @@ impl PrintProcessorBuilder {
 /// ├── b
 /// │   ├── 1
 /// │   └── 2
-/// ├── c
+/// ├── c.tar
+/// │   └╌╌ 3
 /// └── d
 /// ```
-pub struct PrintProcessor {
+pub struct PrintProcessor<W: Write = Stdout> {
+    writer: W,
     dir_has_next: Vec<bool>,
     num_dirs: usize,
     num_files: usize,
     summary_format: SummaryFormat,
+    report: Option<ReportCollector>,
+    compact: bool,
+    columns: Columns,
+    classify: bool,
+    dim_ignored: bool,
+    /// The directory chain opened but not yet printed in compact mode, see `PendingDir`. A
+    /// directory is held back until it's known whether its only child is another directory.
+    pending_dir: Option<PendingDir>,
+    /// For each open directory, whether it was collapsed into its parent's line.
+    collapsed: Vec<bool>,
+    /// Name of each open directory, to label its digest once it's closed.
+    dir_names: Vec<String>,
+    merkle: Option<MerkleStack>,
+    /// Digest of the last directory closed, which in the end is the root.
+    root_digest: Option<Vec<u8>>,
+    highlighted: HashSet<PathBuf>,
 }
 
-impl PrintProcessor {
-    fn print_entry<D: Display>(&mut self, name: &D) {
-        let vertical_line = "│   ";
-        let branched_line = "├── ";
-        let terminal_line = "└── ";
-        let empty_line    = "    ";
-
-        let len = self.dir_has_next.len();
-
-        for (i, has_next) in self.dir_has_next.iter().enumerate() {
-            if i < len - 1 {
-                if *has_next {
-                    print!("{}", vertical_line);
-                } else {
-                    print!("{}", empty_line);
-                }
-            } else if *has_next {
-                print!("{}", branched_line);
+/// A chain of directories printed on one line in compact mode, e.g. `src/com/example`.
+struct PendingDir {
+    /// The columns of the first directory followed by the names of all of them.
+    name: String,
+    /// The first directory. The others share its marks, so they decorate the whole line.
+    entry: Entry,
+}
+
+impl<W: Write> PrintProcessor<W> {
+    /// Consume the processor, returning the writer it wrote to.
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+
+    /// Write `line` followed by a newline. Like `println!`, this panics if writing fails.
+    fn write_line<D: Display + ?Sized>(&mut self, line: &D) {
+        if let Err(err) = writeln!(self.writer, "{}", line) {
+            panic!("Failed to write output: {}", err);
+        }
+    }
+
+    /// The text printed for `entry`, made up of its columns and file name, and its marks.
+    fn label(&mut self, entry: &Entry) -> String {
+        let name = self.name(entry);
+        self.decorate(name, entry)
+    }
+
+    /// The columns and file name of `entry`.
+    fn name(&mut self, entry: &Entry) -> String {
+        let mut name = self.columns.format(entry.metadata());
+        name.push_str(&entry.file_name());
+
+        if self.classify {
+            if let Some(indicator) = columns::indicator(entry.metadata()) {
+                name.push(indicator);
+            }
+        }
+
+        name
+    }
+
+    /// Add the digest and marks of `entry` to `label`, such as whether it's ignored.
+    fn decorate(&self, mut label: String, entry: &Entry) -> String {
+        if let Some(digest) = entry.digest() {
+            label.push_str("  ");
+            label.push_str(&hash::to_hex(digest));
+        }
+
+        if entry.is_mount_point() {
+            label.push_str(" [mount]");
+        }
+
+        if self.highlighted.contains(entry.path()) {
+            label = format!("\x1b[1;32m{}\x1b[0m", label);
+        }
+
+        if entry.is_ignored() {
+            if self.dim_ignored {
+                label = format!("\x1b[2m{}\x1b[0m", label);
             } else {
-                print!("{}", terminal_line);
+                label.push_str(" [ignored]");
             }
         }
 
-        println!("{}", name);
+        label
+    }
+
+    /// Print the pending directory chain, if any, joined into a single line.
+    fn flush_pending_dir(&mut self) {
+        if let Some(pending) = self.pending_dir.take() {
+            let label = self.decorate(pending.name, &pending.entry);
+            self.print_entry(&label, pending.entry.is_archive_member());
+            self.dir_has_next.push(true);
+        }
+    }
+
+    /// Whether `entry` can be added to the chain started by `first`, so that the marks of the
+    /// chain are those of each directory in it. Chains don't span archives, to keep their
+    /// members distinct, and end at mount points.
+    fn joins_chain(&self, first: &Entry, entry: &Entry) -> bool {
+        entry.metadata().is_dir() && entry.is_archive_member() == first.is_archive_member() &&
+        entry.is_ignored() == first.is_ignored() &&
+        self.highlighted.contains(entry.path()) == self.highlighted.contains(first.path()) &&
+        !entry.is_mount_point() && !first.is_mount_point() &&
+        entry.digest().is_none() && first.digest().is_none()
     }
 
-    fn print_summary(&self) {
+    fn print_entry<D: Display>(&mut self, name: &D, archive_member: bool) {
+        let prefix = if archive_member {
+            archive_member_prefix(&self.dir_has_next)
+        } else {
+            tree_prefix(&self.dir_has_next)
+        };
+
+        self.write_line(&format!("{}{}", prefix, name));
+    }
+
+    /// Print the digest of the directory `name` below its entries, lined up with their names.
+    fn print_dir_digest(&mut self, name: &str, digest: &[u8]) {
+        let prefix = prefix(&self.dir_has_next, "    ", "    ");
+        self.write_line(&format!("{}{}/  {}", prefix, name, hash::to_hex(digest)));
+    }
+
+    fn print_summary(&mut self) {
         let dirs = if self.num_dirs == 1 {
             "directory"
         } else {
@@ -118,53 +316,427 @@ impl PrintProcessor {
             "files"
         };
 
-        match self.summary_format {
+        let summary = match self.summary_format {
             SummaryFormat::DirAndFileCount => {
-                println!("\n{} {}, {} {}", self.num_dirs, dirs, self.num_files, files)
+                Some(format!("\n{} {}, {} {}", self.num_dirs, dirs, self.num_files, files))
             },
             SummaryFormat::DirCount => {
-                println!("\n{} {}", self.num_dirs, dirs)
+                Some(format!("\n{} {}", self.num_dirs, dirs))
+            },
+            SummaryFormat::Full => {
+                self.report.as_ref().map(|collector| format!("\n{}", collector.report()))
+            },
+        };
+
+        if let Some(summary) = summary {
+            self.write_line(&summary);
+        }
+
+        let digest = match (self.merkle.as_ref(), self.root_digest.as_ref()) {
+            (Some(merkle), Some(digest)) => {
+                Some(format!("{} {}", merkle.algorithm().name(), hash::to_hex(digest)))
             },
+            _ => None,
+        };
+
+        if let Some(digest) = digest {
+            self.write_line(&digest);
         }
     }
 
 }
 
-fn file_name_from_path(path: &Path) -> Cow<str> {
-    // Using unwrap here should be safe as long as all paths processed by this
-    // function are generated from read_dir
-    path.file_name().unwrap().to_string_lossy()
-}
-
-impl TreeProcessor for PrintProcessor {
+impl<W: Write> TreeProcessor for PrintProcessor<W> {
     fn open_dir(&mut self, entry: &Entry) {
+        self.num_dirs += 1;
+        self.dir_names.push(entry.file_name().into_owned());
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.open_dir(entry);
+        }
+
+        if let Some(ref mut collector) = self.report {
+            collector.open_dir(entry);
+        }
+
+        if self.compact {
+            // A pending directory means this is its first child, and without a next sibling it is
+            // also the only one
+            let collapse = match self.pending_dir {
+                Some(ref pending) => {
+                    !entry.has_next_sibling() && self.joins_chain(&pending.entry, entry)
+                },
+                None => false,
+            };
+
+            if collapse {
+                // Only the first directory of the chain gets columns. With classify, the name
+                // already ends with a slash.
+                if let Some(ref mut pending) = self.pending_dir {
+                    if !self.classify {
+                        pending.name.push('/');
+                    }
+                    pending.name.push_str(&entry.file_name());
+                    if self.classify {
+                        pending.name.push('/');
+                    }
+                }
+                self.collapsed.push(true);
+                return;
+            }
+
+            self.flush_pending_dir();
+            self.dir_has_next.pop();
+            self.dir_has_next.push(entry.has_next_sibling());
+            self.pending_dir = Some(PendingDir {
+                name: self.name(entry),
+                entry: entry.clone(),
+            });
+            self.collapsed.push(false);
+            return;
+        }
+
         self.dir_has_next.pop();
         self.dir_has_next.push(entry.has_next_sibling());
 
         // Print the relative path to the root dir
         if self.dir_has_next.is_empty() {
-            self.print_entry(&entry.path().display());
+            self.print_entry(&entry.path().display(), false);
         } else {
-            self.print_entry(file_name_from_path(entry.path()).to_mut());
+            let label = self.label(entry);
+            self.print_entry(&label, entry.is_archive_member());
         };
 
         self.dir_has_next.push(true);
-        self.num_dirs += 1;
+        self.collapsed.push(false);
     }
 
     fn close_dir(&mut self) {
-        self.dir_has_next.pop().expect("Number of calls to close_dir exceeds open_dir");
+        self.flush_pending_dir();
 
-        if self.dir_has_next.is_empty() {
-            self.print_summary();
+        let digest = self.merkle.as_mut().map(MerkleStack::close_dir);
+
+        // The root has no entry in `dir_names` or `collapsed`, and its digest is printed after
+        // the summary
+        match (self.dir_names.pop(), digest) {
+            (Some(name), Some(digest)) => self.print_dir_digest(&name, &digest),
+            (None, digest) => self.root_digest = digest,
+            _ => {},
+        }
+
+        if self.collapsed.pop() != Some(true) {
+            self.dir_has_next.pop().expect("Number of calls to close_dir exceeds open_dir");
+        }
+
+        if let Some(ref mut collector) = self.report {
+            collector.close_dir();
         }
     }
 
     fn file(&mut self, entry: &Entry) {
+        if let Some(ref mut collector) = self.report {
+            collector.file(entry);
+        }
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        self.flush_pending_dir();
         self.dir_has_next.pop();
         self.dir_has_next.push(entry.has_next_sibling());
 
-        self.print_entry(file_name_from_path(entry.path()).to_mut());
+        let label = self.label(entry);
+        self.print_entry(&label, entry.is_archive_member());
         self.num_files += 1;
     }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        if let Some(ref mut collector) = self.report {
+            collector.limited_dir(entry, num_entries);
+        }
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        self.flush_pending_dir();
+        self.dir_has_next.pop();
+        self.dir_has_next.push(entry.has_next_sibling());
+
+        let label = self.label(entry);
+        self.print_entry(&format!("{} [{} entries exceeds filelimit]", label, num_entries),
+                         entry.is_archive_member());
+        self.num_dirs += 1;
+    }
+
+    fn unreadable_dir(&mut self, error: &ReadDirError) -> bool {
+        let entry = error.entry();
+
+        if let Some(ref mut collector) = self.report {
+            collector.limited_dir(entry, 0);
+        }
+
+        if let Some(ref mut merkle) = self.merkle {
+            merkle.file(entry);
+        }
+
+        self.flush_pending_dir();
+        self.dir_has_next.pop();
+        self.dir_has_next.push(entry.has_next_sibling());
+
+        let label = self.label(entry);
+        self.print_entry(&format!("{} [error opening dir]", label), entry.is_archive_member());
+        self.num_dirs += 1;
+        true
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        // The elision marker is always the last line of its directory
+        self.flush_pending_dir();
+        self.dir_has_next.pop();
+        self.dir_has_next.push(false);
+
+        self.print_entry(&format!("… {} more", num_entries), false);
+    }
+
+    fn finish(&mut self, tree: &EventSource) {
+        if let Some(ref mut collector) = self.report {
+            collector.finish(tree);
+        }
+
+        self.print_summary();
+    }
+}
+
+/// The lines drawn in front of an entry, given whether the entry and each of its ancestors has a
+/// next sibling, outermost first.
+pub fn tree_prefix(dir_has_next: &[bool]) -> String {
+    prefix(dir_has_next, "├── ", "└── ")
+}
+
+/// Like `tree_prefix()`, but with a dashed line to the entry, for archive members.
+pub fn archive_member_prefix(dir_has_next: &[bool]) -> String {
+    prefix(dir_has_next, "├╌╌ ", "└╌╌ ")
+}
+
+fn prefix(dir_has_next: &[bool], branched_line: &str, terminal_line: &str) -> String {
+    let vertical_line = "│   ";
+    let empty_line    = "    ";
+
+    let len = dir_has_next.len();
+    let mut prefix = String::new();
+
+    for (i, has_next) in dir_has_next.iter().enumerate() {
+        if i < len - 1 {
+            if *has_next {
+                prefix.push_str(vertical_line);
+            } else {
+                prefix.push_str(empty_line);
+            }
+        } else if *has_next {
+            prefix.push_str(branched_line);
+        } else {
+            prefix.push_str(terminal_line);
+        }
+    }
+
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::path::{Path, PathBuf};
+    use super::*;
+    use super::super::filters::{self, FileFilter, FilterAggregate};
+    use super::super::metadata::Metadata;
+    use super::super::source::MemorySource;
+    use super::super::tree::TreeIter;
+
+    fn include_all(_path: &Path, _metadata: &Metadata) -> Result<bool, Box<Error>> {
+        Ok(true)
+    }
+
+    /// The output of a `PrintProcessor` set up by `configure`, walking `source`.
+    fn render<F, C>(source: MemorySource, file_filter: F, configure: C) -> String
+        where F: FileFilter + 'static,
+              C: FnOnce(&mut PrintProcessorBuilder, &mut TreeIter)
+    {
+        let mut builder = PrintProcessorBuilder::new(PathBuf::from("."));
+        let mut tree_iter = TreeIter::with_source(source, ".", file_filter).unwrap();
+        configure(&mut builder, &mut tree_iter);
+
+        let mut processor = builder.build_to(Vec::new());
+        assert!(processor.process(&mut tree_iter).is_none());
+        String::from_utf8(processor.into_writer()).unwrap()
+    }
+
+    fn example() -> MemorySource {
+        MemorySource::parse(".", "
+            a
+            b/1
+            b/2
+            c/
+            d -> a
+        ")
+    }
+
+    #[test]
+    fn tree() {
+        assert_eq!(render(example(), include_all, |_, _| {}),
+                   ".\n\
+                    ├── a\n\
+                    ├── b\n\
+                    │   ├── 1\n\
+                    │   └── 2\n\
+                    ├── c\n\
+                    └── d\n\
+                    \n\
+                    2 directories, 4 files\n");
+    }
+
+    #[test]
+    fn classify_and_permissions() {
+        let output = render(example(), include_all, |builder, _| {
+            let mut columns = Columns::default();
+            columns.permissions(true);
+            builder.classify(true).columns(columns);
+        });
+
+        assert_eq!(output,
+                   ".\n\
+                    ├── [-rw-r--r--]  a\n\
+                    ├── [drwxr-xr-x]  b/\n\
+                    │   ├── [-rw-r--r--]  1\n\
+                    │   └── [-rw-r--r--]  2\n\
+                    ├── [drwxr-xr-x]  c/\n\
+                    └── [lrwxrwxrwx]  d@\n\
+                    \n\
+                    2 directories, 4 files\n");
+    }
+
+    #[test]
+    fn unreadable_dirs() {
+        let mut source = MemorySource::parse(".", "
+            a/1
+            b/2/3
+            c/4
+        ");
+        source.fail("b/2").fail("c");
+
+        assert_eq!(render(source, include_all, |_, _| {}),
+                   ".\n\
+                    ├── a\n\
+                    │   └── 1\n\
+                    ├── b\n\
+                    │   └── 2 [error opening dir]\n\
+                    └── c [error opening dir]\n\
+                    \n\
+                    4 directories, 1 file\n");
+    }
+
+    #[test]
+    fn compact() {
+        let source = MemorySource::parse(".", "
+            src/com/example/Main.java
+            src/com/example/Util.java
+            README
+        ");
+        let output = render(source, include_all, |builder, _| {
+            builder.compact(true);
+        });
+
+        assert_eq!(output,
+                   ".\n\
+                    ├── README\n\
+                    └── src/com/example\n\
+                    \x20   ├── Main.java\n\
+                    \x20   └── Util.java\n\
+                    \n\
+                    3 directories, 3 files\n");
+    }
+
+    #[test]
+    fn limits() {
+        let source = MemorySource::parse(".", "
+            big/1
+            big/2
+            big/3
+            e
+            f
+            g
+        ");
+        let output = render(source, include_all, |_, tree_iter| {
+            tree_iter.file_limit(2).max_children(2);
+        });
+
+        assert_eq!(output,
+                   ".\n\
+                    ├── big [3 entries exceeds filelimit]\n\
+                    ├── e\n\
+                    └── … 2 more\n\
+                    \n\
+                    1 directory, 1 file\n");
+    }
+
+    #[test]
+    fn ignored_entries_are_tagged() {
+        let mut filters = FilterAggregate::default();
+        filters.push_annotating("hidden", filters::filter_hidden_files);
+
+        let source = MemorySource::parse(".", "
+            .git/HEAD
+            src
+        ");
+
+        assert_eq!(render(source, filters, |_, _| {}),
+                   ".\n\
+                    ├── .git [ignored]\n\
+                    │   └── HEAD [ignored]\n\
+                    └── src\n\
+                    \n\
+                    1 directory, 2 files\n");
+    }
+
+    #[test]
+    fn only_dirs() {
+        let output = render(example(), filters::filter_non_dirs, |builder, _| {
+            builder.summary(SummaryFormat::DirCount);
+        });
+
+        assert_eq!(output,
+                   ".\n\
+                    ├── b\n\
+                    └── c\n\
+                    \n\
+                    2 directories\n");
+    }
+
+    #[test]
+    fn dir_digests() {
+        let output = render(example(), include_all, |builder, tree_iter| {
+            builder.digests(Algorithm::Sha256);
+            tree_iter.hash(Algorithm::Sha256);
+        });
+        // Digests are checked by the hash module, only their placement matters here, so the
+        // last word of each line is dropped if it's as long as a SHA-256 digest in hex
+        let lines: Vec<_> = output.lines()
+            .map(|line| match line.rfind(' ') {
+                Some(i) if line.len() - i == 65 => &line[..i],
+                _ => line,
+            })
+            .collect();
+
+        assert_eq!(lines,
+                   [".", "├── a ", "├── b", "│   ├── 1 ", "│   └── 2 ", "│       b/ ", "├── c",
+                    "│       c/ ", "└── d ", "", "2 directories, 4 files", "sha256"]);
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(tree_prefix(&[true]), "├── ");
+        assert_eq!(tree_prefix(&[false, true, false]), "    │   └── ");
+        assert_eq!(archive_member_prefix(&[true, true]), "│   ├╌╌ ");
+    }
 }
@@ -0,0 +1,258 @@
+//! Walking trees held in memory.
+//!
+//! A tree read from elsewhere than the file system, such as a saved listing or an archive, can
+//! be built from `Node`s and walked by `TreeIter` through `NodeSource`, so that it's filtered and
+//! limited like any other tree and any `TreeProcessor` can render it.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use super::metadata::{FileType, Metadata};
+use super::source::{DirEntries, ReadSeek, TreeSource};
+use super::tree::Entry;
+
+/// An entry of a tree held in memory.
+pub enum Node {
+    File(NodeInfo),
+    Dir(NodeInfo, Vec<Node>),
+    /// A directory that wasn't descended into, with its number of entries.
+    LimitedDir(NodeInfo, usize),
+    /// A number of entries that were left out.
+    Elided(usize),
+}
+
+/// The name and metadata of a `Node`.
+pub struct NodeInfo {
+    pub name: String,
+    pub metadata: Metadata,
+    /// Whether the entry is marked as ignored, see `Entry::is_ignored()`.
+    pub ignored: bool,
+    /// A known digest, see `Entry::digest()`.
+    pub digest: Option<Vec<u8>>,
+    /// Whether the entry is a member of an archive, see `Entry::is_archive_member()`.
+    pub archive_member: bool,
+    /// Whether the entry is a mount point, see `Entry::is_mount_point()`.
+    pub mount_point: bool,
+}
+
+impl NodeInfo {
+    /// Create the info of an entry that isn't ignored and has no digest, nor any other marks.
+    pub fn new(name: String, metadata: Metadata) -> Self {
+        NodeInfo {
+            name: name,
+            metadata: metadata,
+            ignored: false,
+            digest: None,
+            archive_member: false,
+            mount_point: false,
+        }
+    }
+}
+
+/// A tree of `Node`s, as a `TreeSource`.
+///
+/// Directories are listed in the order of their nodes, with entry paths made of the names of the
+/// nodes joined onto the root. A `Node::Dir` with the metadata of a file, like an expanded
+/// archive, is listed as a file with entries of its own, see `TreeSource::is_expanded()`.
+///
+/// Only metadata is known, so files can't be opened. The marks and digests of the nodes are
+/// attached to their entries by `annotate()`.
+pub struct NodeSource {
+    nodes: HashMap<PathBuf, IndexedNode>,
+}
+
+/// A node of a `NodeSource`, with the paths of its entries rather than the entries themselves.
+struct IndexedNode {
+    info: NodeInfo,
+    /// The paths of the entries of a directory, in order.
+    children: Option<Vec<PathBuf>>,
+    /// The number of entries of a directory that wasn't descended into.
+    num_entries: Option<usize>,
+    /// The number of entries left out of a directory.
+    num_elided: usize,
+}
+
+impl NodeSource {
+    /// Index `contents`, the entries of the root directory at `root`.
+    ///
+    /// Fails if the name of a node isn't a single file name, like `..` or `a/b`, or if two nodes
+    /// in the same directory have the same name.
+    pub fn new(root: PathBuf, contents: Vec<Node>) -> Result<Self, Box<Error>> {
+        let mut source = NodeSource { nodes: HashMap::new() };
+        let info = NodeInfo::new(String::new(), Metadata::new(FileType::Dir));
+
+        try!(source.insert(root, info, Some(contents), None));
+        Ok(source)
+    }
+
+    fn insert(&mut self,
+              path: PathBuf,
+              info: NodeInfo,
+              contents: Option<Vec<Node>>,
+              num_entries: Option<usize>)
+              -> Result<(), Box<Error>> {
+        let mut num_elided = 0;
+        let mut children = None;
+
+        if let Some(contents) = contents {
+            let mut paths = Vec::with_capacity(contents.len());
+
+            for node in contents {
+                let (info, contents, num_entries) = match node {
+                    Node::File(info) => (info, None, None),
+                    Node::Dir(info, contents) => (info, Some(contents), None),
+                    Node::LimitedDir(info, num_entries) => (info, None, Some(num_entries)),
+                    Node::Elided(count) => {
+                        num_elided += count;
+                        continue;
+                    },
+                };
+
+                let mut components = Path::new(&info.name).components();
+
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => {},
+                    _ => {
+                        return Err(From::from(format!("Invalid entry name '{}' in '{}'",
+                                                      info.name,
+                                                      path.display())))
+                    },
+                }
+
+                let child = path.join(&info.name);
+
+                if self.nodes.contains_key(&child) {
+                    return Err(From::from(format!("Duplicate entry '{}'", child.display())));
+                }
+
+                paths.push(child.clone());
+                try!(self.insert(child, info, contents, num_entries));
+            }
+
+            children = Some(paths);
+        }
+
+        self.nodes.insert(path,
+                          IndexedNode {
+                              info: info,
+                              children: children,
+                              num_entries: num_entries,
+                              num_elided: num_elided,
+                          });
+        Ok(())
+    }
+
+    fn node(&self, path: &Path) -> io::Result<&IndexedNode> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such file or directory"))
+    }
+}
+
+impl TreeSource for NodeSource {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.node(path).map(|node| node.info.metadata.clone())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<DirEntries> {
+        let children = match try!(self.node(path)).children {
+            Some(ref children) => children,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")),
+        };
+        let entries: Vec<_> = children.iter()
+            .map(|child| Ok((child.clone(), self.nodes[child].info.metadata.clone())))
+            .collect();
+
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn open(&self, _path: &Path) -> io::Result<Box<ReadSeek>> {
+        Err(unavailable())
+    }
+
+    fn read_link(&self, _path: &Path) -> io::Result<PathBuf> {
+        Err(unavailable())
+    }
+
+    fn has_contents(&self) -> bool {
+        false
+    }
+
+    fn is_expanded(&self, path: &Path) -> bool {
+        self.nodes
+            .get(path)
+            .map_or(false, |node| node.children.is_some() && !node.info.metadata.is_dir())
+    }
+
+    fn limited_dir(&self, path: &Path) -> Option<usize> {
+        self.nodes.get(path).and_then(|node| node.num_entries)
+    }
+
+    fn num_elided(&self, path: &Path) -> usize {
+        self.nodes.get(path).map_or(0, |node| node.num_elided)
+    }
+
+    fn annotate(&self, entry: &mut Entry) {
+        let info = match self.nodes.get(entry.path()) {
+            Some(node) => &node.info,
+            None => return,
+        };
+
+        if let Some(ref digest) = info.digest {
+            entry.set_digest(digest.clone());
+        }
+
+        if info.ignored {
+            entry.set_ignored(true);
+        }
+
+        if info.archive_member {
+            entry.set_archive_member(true);
+        }
+
+        if info.mount_point {
+            entry.set_mount_point(true);
+        }
+    }
+}
+
+fn unavailable() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "Only the metadata of the tree is known")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn file(name: &str) -> Node {
+        Node::File(NodeInfo::new(String::from(name), Metadata::new(FileType::File)))
+    }
+
+    fn error(contents: Vec<Node>) -> String {
+        match NodeSource::new(PathBuf::from("root"), contents) {
+            Ok(_) => String::from("no error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert_eq!(error(vec![file("")]), "Invalid entry name '' in 'root'");
+        assert_eq!(error(vec![file(".")]), "Invalid entry name '.' in 'root'");
+        assert_eq!(error(vec![file("..")]), "Invalid entry name '..' in 'root'");
+        assert_eq!(error(vec![file("a/b")]), "Invalid entry name 'a/b' in 'root'");
+        assert_eq!(error(vec![file("/a")]), "Invalid entry name '/a' in 'root'");
+
+        let dir = NodeInfo::new(String::from("dir"), Metadata::new(FileType::Dir));
+        assert_eq!(error(vec![Node::Dir(dir, vec![file("..")])]),
+                   "Invalid entry name '..' in 'root/dir'");
+    }
+
+    #[test]
+    fn duplicate_names() {
+        assert_eq!(error(vec![file("a"), file("b"), file("a")]), "Duplicate entry 'root/a'");
+        assert_eq!(error(vec![file("a"), file("b")]), "no error");
+    }
+}
@@ -0,0 +1,206 @@
+//! Statistics about a walked tree.
+//!
+//! `ReportCollector` is a `TreeProcessor` gathering a `Report`, which can be inspected
+//! programmatically or displayed as a human readable summary.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::tree::{Entry, EventSource};
+use super::tree_processor::TreeProcessor;
+use super::filters::{FilterStats, Rejections};
+
+/// The number of largest files kept in a `Report`.
+pub const NUM_LARGEST_FILES: usize = 5;
+
+/// Statistics about a tree.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Number of directories, not including the root.
+    pub num_dirs: usize,
+    /// Number of non-directory files, including symlinks.
+    pub num_files: usize,
+    /// Number of symlinks.
+    pub num_symlinks: usize,
+    /// Sum of the sizes of all files in bytes.
+    pub total_bytes: u64,
+    /// Number of files per extension. Files without an extension are counted under the empty
+    /// string.
+    pub extensions: BTreeMap<String, usize>,
+    /// The largest files and their sizes in bytes, largest first.
+    pub largest_files: Vec<(PathBuf, u64)>,
+    /// The first entry found at `max_depth`.
+    pub deepest_path: Option<PathBuf>,
+    /// Depth of the deepest entry, where the root's children are at depth 1.
+    pub max_depth: usize,
+    /// Number of entries left out by filters.
+    pub num_filtered: usize,
+    /// Entries left out by each filter, if the collector was given the filter statistics.
+    pub rejections: Vec<Rejections>,
+    /// Time elapsed while walking the tree.
+    pub elapsed: Duration,
+}
+
+fn plural<'a>(count: usize, singular: &'a str, plural: &'a str) -> &'a str {
+    if count == 1 {
+        singular
+    } else {
+        plural
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(writeln!(formatter,
+                      "{} {}, {} {}, {} {}",
+                      self.num_dirs,
+                      plural(self.num_dirs, "directory", "directories"),
+                      self.num_files,
+                      plural(self.num_files, "file", "files"),
+                      self.num_symlinks,
+                      plural(self.num_symlinks, "symlink", "symlinks")));
+        try!(writeln!(formatter, "{} bytes in total", self.total_bytes));
+
+        if !self.extensions.is_empty() {
+            let mut extensions: Vec<_> = self.extensions.iter().collect();
+            // Most common first, ties in alphabetical order
+            extensions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+            let extensions: Vec<_> = extensions.iter()
+                .map(|&(extension, count)| {
+                    if extension.is_empty() {
+                        format!("(none) {}", count)
+                    } else {
+                        format!(".{} {}", extension, count)
+                    }
+                })
+                .collect();
+            try!(writeln!(formatter, "Extensions: {}", extensions.join(", ")));
+        }
+
+        if !self.largest_files.is_empty() {
+            try!(writeln!(formatter, "Largest files:"));
+            for &(ref path, size) in &self.largest_files {
+                try!(writeln!(formatter, "    {:>12}  {}", size, path.display()));
+            }
+        }
+
+        if let Some(ref path) = self.deepest_path {
+            try!(writeln!(formatter, "Deepest path: {} (depth {})", path.display(), self.max_depth));
+        }
+
+        try!(writeln!(formatter,
+                      "{} {} filtered out",
+                      self.num_filtered,
+                      plural(self.num_filtered, "entry", "entries")));
+
+        for rejections in &self.rejections {
+            try!(writeln!(formatter, "    {:>12}  {}", rejections.count, rejections.name));
+        }
+
+        write!(formatter,
+               "Elapsed time: {}.{:03}s",
+               self.elapsed.as_secs(),
+               self.elapsed.subsec_nanos() / 1_000_000)
+    }
+}
+
+/// A `TreeProcessor` collecting a `Report`.
+pub struct ReportCollector {
+    report: Report,
+    depth: usize,
+    start: Instant,
+    filter_stats: Option<FilterStats>,
+}
+
+impl ReportCollector {
+    /// Create a new collector. The elapsed time is measured from this call.
+    pub fn new() -> Self {
+        ReportCollector {
+            report: Report::default(),
+            depth: 0,
+            start: Instant::now(),
+            filter_stats: None,
+        }
+    }
+
+    /// Include the rejections of each filter in the report.
+    pub fn filter_stats(&mut self, stats: FilterStats) -> &mut Self {
+        self.filter_stats = Some(stats);
+        self
+    }
+
+    /// The statistics collected so far.
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    fn visit(&mut self, path: &Path, depth: usize) {
+        if depth > self.report.max_depth {
+            self.report.max_depth = depth;
+            self.report.deepest_path = Some(path.to_path_buf());
+        }
+    }
+}
+
+impl Default for ReportCollector {
+    fn default() -> Self {
+        ReportCollector::new()
+    }
+}
+
+impl TreeProcessor for ReportCollector {
+    fn open_dir(&mut self, entry: &Entry) {
+        self.depth += 1;
+        self.visit(entry.path(), self.depth);
+        self.report.num_dirs += 1;
+    }
+
+    fn close_dir(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        let depth = self.depth + 1;
+        self.visit(entry.path(), depth);
+
+        let metadata = entry.metadata();
+        let report = &mut self.report;
+
+        report.num_files += 1;
+
+        if metadata.file_type().is_symlink() {
+            report.num_symlinks += 1;
+        }
+
+        let extension = entry.path()
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        *report.extensions.entry(extension).or_insert(0) += 1;
+
+        let size = metadata.len();
+        report.total_bytes += size;
+
+        let position = report.largest_files
+            .iter()
+            .position(|&(_, other_size)| size > other_size)
+            .unwrap_or(report.largest_files.len());
+
+        if position < NUM_LARGEST_FILES {
+            report.largest_files.insert(position, (entry.path().to_path_buf(), size));
+            report.largest_files.truncate(NUM_LARGEST_FILES);
+        }
+    }
+
+    fn finish(&mut self, tree: &EventSource) {
+        self.report.num_filtered = tree.num_filtered();
+
+        if let Some(ref stats) = self.filter_stats {
+            self.report.rejections = stats.rejections();
+        }
+
+        self.report.elapsed = self.start.elapsed();
+    }
+}
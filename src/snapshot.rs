@@ -0,0 +1,247 @@
+//! Saving snapshots of trees, and loading them for comparison.
+//!
+//! # Format
+//!
+//! A snapshot is a text file starting with a header line holding the format version and the hash
+//! algorithm used, see `hash::Algorithm`, or `-` if the snapshot has no hashes:
+//!
+//! ```text
+//! oak-snapshot 1 sha256
+//! ```
+//!
+//! It's followed by a line per entry, with directories before their contents:
+//!
+//! ```text
+//! d 4096 0755 1476871200 - src
+//! f 1052 0644 1476871200 9f86d081884c7d65...0f00a08 src/main.rs
+//! ```
+//!
+//! The fields are the type, size in bytes, permission bits in octal, modification time in seconds
+//! since the epoch, hex encoded digest or `-`, and path relative to the root. Paths are
+//! written as raw bytes, except that backslashes and control characters are escaped as `\xNN`.
+//! Types are `f` for regular files, `d` for directories, `l` for symlinks, `p` for FIFOs, `s` for
+//! sockets, and `b` and `c` for block and character devices.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{BufRead, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::str;
+use super::diff::Node;
+use super::hash::{self, Algorithm};
+use super::metadata::{FileType, Metadata};
+use super::source::TreeSource;
+use super::tree::{Entry, Event, EventSource};
+
+/// The version of the format written by `save()`.
+pub const VERSION: u32 = 1;
+
+/// The first word of the header line.
+const MAGIC: &'static str = "oak-snapshot";
+
+/// Write a snapshot of the tree in `events`, rooted at `root`, to `writer`.
+///
+/// With an `algorithm`, the digest of each entry is recorded. Digests are taken from the events
+/// if present, see `TreeIter::hash()`, and otherwise computed from the source of `events`.
+/// Entries whose contents can't be read, like archive members, are recorded without a digest.
+/// Returns the number of entries written.
+pub fn save<W: Write>(events: &mut EventSource,
+                      root: &Path,
+                      mut writer: W,
+                      algorithm: Option<Algorithm>)
+                      -> Result<usize, Box<Error>> {
+    let algorithm_name = algorithm.map_or("-", |algorithm| algorithm.name());
+    try!(writeln!(writer, "{} {} {}", MAGIC, VERSION, algorithm_name));
+    let source = events.source();
+    let mut num_entries = 0;
+
+    while let Some(result) = events.next() {
+        match try!(result) {
+            Event::OpenDir(entry) | Event::File(entry) | Event::LimitedDir(entry, _) => {
+                try!(write_entry(&mut writer, &*source, &entry, root, algorithm));
+                num_entries += 1;
+            },
+            Event::CloseDir | Event::Elided(_) => {},
+        }
+    }
+
+    try!(writer.flush());
+    Ok(num_entries)
+}
+
+fn write_entry<W: Write>(writer: &mut W,
+                         source: &TreeSource,
+                         entry: &Entry,
+                         root: &Path,
+                         algorithm: Option<Algorithm>)
+                         -> Result<(), Box<Error>> {
+    let metadata = entry.metadata();
+    let digest = match (entry.digest(), algorithm) {
+        (Some(digest), Some(_)) => Some(digest.to_vec()),
+        (None, Some(algorithm)) if source.has_contents() && !entry.is_archive_member() => {
+            try!(hash::source_digest(source, entry.path(), metadata, algorithm))
+        },
+        (None, Some(_)) => None,
+        (_, None) => None,
+    };
+    let digest = digest.map_or_else(|| String::from("-"), |digest| hash::to_hex(&digest));
+    let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+    try!(write!(writer,
+                "{} {} {:04o} {} {} ",
+                type_char(metadata.file_type()),
+                metadata.len(),
+                metadata.mode(),
+                metadata.mtime(),
+                digest));
+    try!(writer.write_all(&escape(relative.as_os_str().as_bytes())));
+    try!(writer.write_all(b"\n"));
+
+    Ok(())
+}
+
+/// A tree loaded from a snapshot.
+pub struct Snapshot {
+    tree: Node,
+    algorithm: Option<Algorithm>,
+}
+
+impl Snapshot {
+    /// Load a snapshot written by `save()`, with the paths of its entries prefixed by `root`.
+    pub fn load<R: BufRead>(reader: R, root: &Path) -> Result<Self, Box<Error>> {
+        let mut lines = reader.split(b'\n');
+
+        let header = match lines.next() {
+            Some(header) => try!(header),
+            None => return Err(From::from("Empty snapshot")),
+        };
+        let header = try!(str::from_utf8(&header));
+        let fields: Vec<_> = header.split(' ').collect();
+
+        if fields.len() != 3 || fields[0] != MAGIC {
+            return Err(From::from("Not an oak snapshot"));
+        }
+
+        if fields[1] != VERSION.to_string() {
+            return Err(From::from(format!("Unsupported snapshot version {}, expected {}",
+                                          fields[1],
+                                          VERSION)));
+        }
+
+        let algorithm = match fields[2] {
+            "-" => None,
+            name => {
+                Some(try!(Algorithm::from_name(name)
+                    .ok_or_else(|| format!("Unsupported hash algorithm '{}'", name))))
+            },
+        };
+
+        let mut tree = Node::new(root.to_path_buf(), Metadata::new(FileType::Dir));
+
+        for (i, line) in lines.enumerate() {
+            let line = try!(line);
+
+            // The header is line 1
+            let (relative, node) = try!(parse_entry(&line, root)
+                .map_err(|err| format!("Line {}: {}", i + 2, err)));
+            let parent = relative.parent().unwrap_or(Path::new(""));
+            try!(tree.insert_at(parent, node));
+        }
+
+        Ok(Snapshot {
+            tree: tree,
+            algorithm: algorithm,
+        })
+    }
+
+    /// The tree, with its root at the path given to `load()`.
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    /// The algorithm of the digests in the snapshot, if it has any.
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        self.algorithm
+    }
+}
+
+/// Parse an entry line, returning the relative path along with the node.
+fn parse_entry(line: &[u8], root: &Path) -> Result<(PathBuf, Node), Box<Error>> {
+    let mut fields = line.splitn(6, |&byte| byte == b' ');
+    let mut next_field = || fields.next().ok_or("Too few fields");
+
+    let file_type = try!(from_type_char(try!(next_field())));
+    let mut metadata = Metadata::new(file_type);
+    metadata.len = try!(try!(str::from_utf8(try!(next_field()))).parse());
+    metadata.mode = try!(u32::from_str_radix(try!(str::from_utf8(try!(next_field()))), 8));
+    metadata.mtime = try!(try!(str::from_utf8(try!(next_field()))).parse());
+    let digest = try!(str::from_utf8(try!(next_field())));
+    let relative = PathBuf::from(OsString::from_vec(try!(unescape(try!(next_field())))));
+
+    let mut node = Node::new(root.join(&relative), metadata);
+
+    if digest != "-" {
+        node.set_digest(try!(hash::from_hex(digest)));
+    }
+
+    Ok((relative, node))
+}
+
+fn type_char(file_type: FileType) -> char {
+    match file_type {
+        FileType::File => 'f',
+        FileType::Dir => 'd',
+        FileType::Symlink => 'l',
+        FileType::Fifo => 'p',
+        FileType::Socket => 's',
+        FileType::BlockDevice => 'b',
+        FileType::CharDevice => 'c',
+    }
+}
+
+fn from_type_char(field: &[u8]) -> Result<FileType, Box<Error>> {
+    match field {
+        b"f" => Ok(FileType::File),
+        b"d" => Ok(FileType::Dir),
+        b"l" => Ok(FileType::Symlink),
+        b"p" => Ok(FileType::Fifo),
+        b"s" => Ok(FileType::Socket),
+        b"b" => Ok(FileType::BlockDevice),
+        b"c" => Ok(FileType::CharDevice),
+        _ => Err(From::from(format!("Unknown type '{}'", String::from_utf8_lossy(field)))),
+    }
+}
+
+/// Escape backslashes and control characters in `bytes` as `\xNN`.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+
+    for &byte in bytes {
+        if byte == b'\\' || byte < 0x20 || byte == 0x7f {
+            escaped.extend_from_slice(format!("\\x{:02x}", byte).as_bytes());
+        } else {
+            escaped.push(byte);
+        }
+    }
+
+    escaped
+}
+
+fn unescape(bytes: &[u8]) -> Result<Vec<u8>, Box<Error>> {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let hex = try!(bytes.get(i + 2..i + 4).ok_or("Truncated escape in path"));
+            unescaped.push(try!(u8::from_str_radix(try!(str::from_utf8(hex)), 16)));
+            i += 4;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Ok(unescaped)
+}
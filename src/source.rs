@@ -0,0 +1,420 @@
+//! Sources of trees to walk.
+//!
+//! `TreeIter` walks a `TreeSource` rather than the file system directly, so that any tree that
+//! can list its directories and read its files gets the same filtering, limits, hashing and
+//! archive expansion. `FsSource` is the file system, and `MemorySource` a tree held in memory,
+//! mostly useful for testing. Saved listings and archives are walked through
+//! `replay::NodeSource`, which only knows metadata.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use super::metadata::{FileType, Metadata};
+use super::tree::Entry;
+
+/// The entries of a directory along with their metadata, see `TreeSource::read_dir()`.
+pub type DirEntries = Box<Iterator<Item=io::Result<(PathBuf, Metadata)>>>;
+
+/// A reader of file contents that can seek, as needed to read zip archives.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A tree of files that can be walked by `TreeIter`.
+///
+/// Paths are given as walked, i.e. the root followed by the names of the entries leading to the
+/// file. Metadata is never taken from symlink targets, except by `target_metadata()`.
+pub trait TreeSource {
+    /// The metadata of the entry at `path`.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// The metadata of the entry at `path`, following symlinks, e.g. to inspect the root.
+    fn target_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let mut path = path.to_path_buf();
+
+        // Give up on symlink loops like the file system does
+        for _ in 0..MAX_SYMLINKS {
+            let metadata = try!(self.metadata(&path));
+
+            if !metadata.file_type().is_symlink() {
+                return Ok(metadata);
+            }
+
+            let target = try!(self.read_link(&path));
+            path = path.parent().map_or_else(|| target.clone(), |parent| parent.join(&target));
+        }
+
+        Err(io::Error::new(io::ErrorKind::Other, "Too many levels of symbolic links"))
+    }
+
+    /// The entries of the directory at `path`, in no particular order. Entry paths are their
+    /// names joined onto `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<DirEntries>;
+
+    /// Open the regular file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<ReadSeek>>;
+
+    /// The target of the symlink at `path`.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Whether `open()` and `read_link()` can read the contents of entries. Sources that only
+    /// know metadata, like saved listings, return false, so that their files aren't hashed.
+    fn has_contents(&self) -> bool {
+        true
+    }
+
+    /// Whether the file at `path` is listed with entries of its own, like an archive that was
+    /// expanded when the tree was read. `TreeIter` descends into such files like directories.
+    fn is_expanded(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// The number of entries of the directory at `path`, if the source only knows how many there
+    /// are, e.g. because the tree was read with a file limit. `TreeIter` yields such directories
+    /// as `Event::LimitedDir`.
+    fn limited_dir(&self, _path: &Path) -> Option<usize> {
+        None
+    }
+
+    /// The number of entries of the directory at `path` known to be left out of `read_dir()`,
+    /// e.g. because the tree was read with a maximum number of children.
+    fn num_elided(&self, _path: &Path) -> usize {
+        0
+    }
+
+    /// Attach what the source knows about `entry` beyond its metadata, such as a digest or
+    /// whether it's ignored.
+    fn annotate(&self, _entry: &mut Entry) {}
+}
+
+/// The file system, as a `TreeSource`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsSource;
+
+impl TreeSource for FsSource {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Metadata::from)
+    }
+
+    fn target_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<DirEntries> {
+        let dir = try!(fs::read_dir(path));
+
+        Ok(Box::new(dir.map(|entry| {
+            let entry = try!(entry);
+            let metadata = try!(entry.metadata());
+            Ok((entry.path(), Metadata::from(metadata)))
+        })))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<ReadSeek>> {
+        Ok(Box::new(try!(File::open(path))))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+}
+
+/// Number of symlinks followed before `TreeSource::target_metadata()` gives up, as in Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// A tree held in memory, as a `TreeSource`.
+///
+/// Directories are listed sorted by name, so walks are deterministic. Unless given other
+/// metadata with `entry()`, files get mode 0644, directories 0755 and symlinks 0777, each entry
+/// gets a distinct inode number, and all other metadata is zero.
+///
+/// # Example
+/// A tree can be built from a description with one entry per line, where directories end with a
+/// slash, symlinks are written `link -> target` and files may be given contents after ` = `.
+/// Missing parent directories are created as needed.
+///
+/// ```
+/// use oak::source::MemorySource;
+///
+/// let source = MemorySource::parse(".", "
+///     src/main.rs = fn main() {}
+///     src/empty/
+///     README
+///     latest -> src/main.rs
+/// ");
+/// ```
+pub struct MemorySource {
+    root: PathBuf,
+    entries: BTreeMap<PathBuf, MemoryEntry>,
+    next_ino: u64,
+}
+
+struct MemoryEntry {
+    metadata: Metadata,
+    /// Contents of files, or the target of symlinks.
+    data: Vec<u8>,
+    failing: bool,
+}
+
+impl MemorySource {
+    /// Create an empty tree rooted at `root`. Entry paths are relative to the root.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        let mut source = MemorySource {
+            root: root.into(),
+            entries: BTreeMap::new(),
+            next_ino: 1,
+        };
+
+        let root = source.root.clone();
+        source.insert(root, FileType::Dir, 0o755, Vec::new());
+        source
+    }
+
+    /// Create a tree rooted at `root` from `description`, see the example above.
+    pub fn parse<P: Into<PathBuf>>(root: P, description: &str) -> Self {
+        let mut source = MemorySource::new(root);
+
+        for line in description.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(index) = line.find(" -> ") {
+                source.symlink(&line[..index], &line[index + 4..]);
+            } else if let Some(index) = line.find(" = ") {
+                source.file(&line[..index], line[index + 3..].as_bytes());
+            } else if line.ends_with('/') {
+                source.dir(line.trim_end_matches('/'));
+            } else {
+                source.file(line, b"");
+            }
+        }
+
+        source
+    }
+
+    /// Add a directory.
+    pub fn dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        let path = self.root.join(path);
+        self.insert(path, FileType::Dir, 0o755, Vec::new());
+        self
+    }
+
+    /// Add a regular file holding `contents`.
+    pub fn file<P: AsRef<Path>>(&mut self, path: P, contents: &[u8]) -> &mut Self {
+        let path = self.root.join(path);
+        self.insert(path, FileType::File, 0o644, contents.to_vec());
+        self
+    }
+
+    /// Add a symlink pointing to `target`.
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(&mut self, path: P, target: T) -> &mut Self {
+        let path = self.root.join(path);
+        let target = target.as_ref().as_os_str().as_bytes().to_vec();
+        self.insert(path, FileType::Symlink, 0o777, target);
+        self
+    }
+
+    /// Add an entry with the given metadata, and contents or symlink target.
+    pub fn entry<P: AsRef<Path>>(&mut self, path: P, metadata: Metadata, data: &[u8]) -> &mut Self {
+        let path = self.root.join(path);
+        self.create_parents(&path);
+        self.entries.insert(path, MemoryEntry {
+            metadata: metadata,
+            data: data.to_vec(),
+            failing: false,
+        });
+        self
+    }
+
+    /// Make reading the directory or file at `path` fail, as if permission was denied. Other
+    /// entries than directories also fail to be listed, as if they vanished before their metadata
+    /// was read.
+    pub fn fail<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        if let Some(entry) = self.entries.get_mut(&self.root.join(path)) {
+            entry.failing = true;
+        }
+        self
+    }
+
+    /// The root, as given when the tree was created.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn insert(&mut self, path: PathBuf, file_type: FileType, mode: u32, data: Vec<u8>) {
+        self.create_parents(&path);
+
+        let mut metadata = Metadata::new(file_type);
+        metadata.len = data.len() as u64;
+        metadata.mode = mode;
+        metadata.ino = self.next_ino;
+        self.next_ino += 1;
+
+        self.entries.insert(path, MemoryEntry {
+            metadata: metadata,
+            data: data,
+            failing: false,
+        });
+    }
+
+    fn create_parents(&mut self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            if parent.starts_with(&self.root) && !self.entries.contains_key(parent) {
+                self.insert(parent.to_path_buf(), FileType::Dir, 0o755, Vec::new());
+            }
+        }
+    }
+
+    /// The entry at `path`, or an error if it doesn't exist or reading it should fail.
+    fn get(&self, path: &Path) -> io::Result<&MemoryEntry> {
+        match self.entries.get(path) {
+            Some(entry) if entry.failing => {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "Permission denied"))
+            },
+            Some(entry) => Ok(entry),
+            None => Err(not_found()),
+        }
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+impl TreeSource for MemorySource {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.entries.get(path) {
+            Some(entry) => Ok(entry.metadata.clone()),
+            None => Err(not_found()),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<DirEntries> {
+        let dir = try!(self.get(path));
+
+        if !dir.metadata.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Not a directory"));
+        }
+
+        let children: Vec<_> = self.entries
+            .iter()
+            .filter(|&(child, _)| child.parent() == Some(path))
+            .map(|(child, entry)| if entry.failing && !entry.metadata.is_dir() {
+                Err(not_found())
+            } else {
+                Ok((child.clone(), entry.metadata.clone()))
+            })
+            .collect();
+
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<ReadSeek>> {
+        let file = try!(self.get(path));
+
+        if file.metadata.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Is a directory"));
+        }
+
+        Ok(Box::new(Cursor::new(file.data.clone())))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let link = try!(self.get(path));
+
+        if !link.metadata.file_type().is_symlink() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a symlink"));
+        }
+
+        Ok(PathBuf::from(OsStr::from_bytes(&link.data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read};
+    use std::path::{Path, PathBuf};
+    use super::*;
+
+    fn names(source: &MemorySource, path: &str) -> Vec<String> {
+        source.read_dir(Path::new(path))
+            .unwrap()
+            .map(|entry| entry.unwrap().0.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let source = MemorySource::parse("root", "
+            src/main.rs = fn main() {}
+            src/empty/
+            README
+            latest -> src/main.rs
+        ");
+
+        assert_eq!(names(&source, "root"), ["root/README", "root/latest", "root/src"]);
+        assert_eq!(names(&source, "root/src"), ["root/src/empty", "root/src/main.rs"]);
+        assert!(names(&source, "root/src/empty").is_empty());
+
+        let mut contents = String::new();
+        source.open(Path::new("root/src/main.rs")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "fn main() {}");
+
+        assert_eq!(source.read_link(Path::new("root/latest")).unwrap(),
+                   PathBuf::from("src/main.rs"));
+    }
+
+    #[test]
+    fn metadata() {
+        let source = MemorySource::parse(".", "
+            dir/
+            file = 12345
+            link -> file
+        ");
+        let metadata = |path| source.metadata(Path::new(path)).unwrap();
+
+        assert!(metadata(".").is_dir());
+        assert!(metadata("./dir").is_dir());
+        assert_eq!(metadata("./dir").mode(), 0o755);
+        assert!(metadata("./file").is_file());
+        assert_eq!(metadata("./file").len(), 5);
+        assert_eq!(metadata("./file").mode(), 0o644);
+        assert!(metadata("./link").file_type().is_symlink());
+        assert!(metadata("./file").ino() != metadata("./link").ino());
+    }
+
+    #[test]
+    fn target_metadata() {
+        let source = MemorySource::parse(".", "
+            dir/file
+            dir/same -> file
+            link -> dir/same
+            loop -> loop
+        ");
+        let metadata = |path| source.target_metadata(Path::new(path));
+
+        // Targets are relative to the directory of the link
+        assert_eq!(metadata("./dir/same").unwrap().ino(), metadata("./dir/file").unwrap().ino());
+        assert_eq!(metadata("./link").unwrap().ino(), metadata("./dir/file").unwrap().ino());
+        assert!(metadata("./dir").unwrap().is_dir());
+        assert!(metadata("./loop").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut source = MemorySource::parse(".", "
+            locked/secret
+            file
+        ");
+        source.fail("locked");
+
+        let kind = |result: io::Result<DirEntries>| result.err().map(|err| err.kind());
+
+        assert_eq!(kind(source.read_dir(Path::new("./locked"))),
+                   Some(ErrorKind::PermissionDenied));
+        assert_eq!(kind(source.read_dir(Path::new("./missing"))), Some(ErrorKind::NotFound));
+        assert!(source.read_dir(Path::new("./file")).is_err());
+        assert!(source.open(Path::new("./locked")).is_err());
+        assert!(source.read_link(Path::new("./file")).is_err());
+    }
+}
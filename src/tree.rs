@@ -1,14 +1,22 @@
 //! Types for recursively walking the file system tree.
 
-use std::fs;
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use std::error::Error;
-use std::iter::Peekable;
+use std::iter::{self, Peekable};
 use std::rc::Rc;
+use std::cell::Cell;
+use std::collections::VecDeque;
 use std::fmt;
-use super::filters::FileFilter;
+use std::io;
+use std::mem;
+use super::filters::{FileFilter, Verdict};
+use super::metadata::Metadata;
+use super::hash::{self, Algorithm};
+use super::archive::{ArchiveTree, Format};
+use super::source::{DirEntries, FsSource, TreeSource};
 
-/// Events yielded from `TreeIter`.
+/// Events yielded from an `EventSource`, such as `TreeIter`.
 #[derive(Debug)]
 pub enum Event {
     /// Any non-direcotry file in the current directory.
@@ -17,21 +25,52 @@ pub enum Event {
     OpenDir(Entry),
     /// Signals end of current directory. The parent becomes the new current directory.
     CloseDir,
+    /// A directory within the current directory that was not descended into, because it contains
+    /// more entries than the file limit. The count is the number of entries in the directory.
+    LimitedDir(Entry, usize),
+    /// Signals that the remaining entries of the current directory were left out, because the
+    /// directory contains more children than allowed. The count is the number of elided entries.
+    Elided(usize),
 }
 
-/// Represents an entry in the file system.
+/// Represents an entry in a tree, usually the file system.
+#[derive(Clone)]
 pub struct Entry {
     path: PathBuf,
     has_next_sibling: bool,
-    metadata: fs::Metadata,
+    metadata: Metadata,
+    ignored: bool,
+    digest: Option<Vec<u8>>,
+    archive_member: bool,
+    mount_point: bool,
 }
 
 impl Entry {
+    /// Create an entry for `path`. Only needed when implementing an `EventSource`.
+    pub fn new(path: PathBuf, metadata: Metadata, has_next_sibling: bool, ignored: bool) -> Self {
+        Entry {
+            path: path,
+            has_next_sibling: has_next_sibling,
+            metadata: metadata,
+            ignored: ignored,
+            digest: None,
+            archive_member: false,
+            mount_point: false,
+        }
+    }
+
     /// Path to the entry, relative to its root.
     pub fn path(&self) -> &Path {
         self.path.as_path()
     }
 
+    /// The file name of the entry, lossily converted to UTF-8.
+    pub fn file_name(&self) -> Cow<str> {
+        // Using unwrap here should be safe as long as all paths are generated from read_dir, or
+        // joined with a name by other sources
+        self.path.file_name().unwrap().to_string_lossy()
+    }
+
     /// Whether the iterator that yielded this entry has more sibling (same directory) entries.
     pub fn has_next_sibling(&self) -> bool {
         self.has_next_sibling
@@ -39,9 +78,53 @@ impl Entry {
 
     /// A cached metadata entry for this file. It's probably better to use this than
     /// calling `fs::metadata` on `path`.
-    pub fn metadata(&self) -> &fs::Metadata {
+    pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
+
+    /// Whether a filter marked this entry as ignored instead of excluding it, see
+    /// `filters::Verdict::Annotate`. Entries within an ignored directory are ignored too.
+    pub fn is_ignored(&self) -> bool {
+        self.ignored
+    }
+
+    /// Set whether the entry is ignored.
+    pub fn set_ignored(&mut self, ignored: bool) {
+        self.ignored = ignored;
+    }
+
+    /// The digest of the entry, if hashing is enabled. See `hash::entry_digest()` for which
+    /// entries have digests.
+    pub fn digest(&self) -> Option<&[u8]> {
+        self.digest.as_ref().map(Vec::as_slice)
+    }
+
+    /// Set the digest of the entry.
+    pub fn set_digest(&mut self, digest: Vec<u8>) {
+        self.digest = Some(digest);
+    }
+
+    /// Whether the entry is a member of an archive rather than a file on disk, see
+    /// `TreeIter::expand_archives()`.
+    pub fn is_archive_member(&self) -> bool {
+        self.archive_member
+    }
+
+    /// Set whether the entry is a member of an archive.
+    pub fn set_archive_member(&mut self, archive_member: bool) {
+        self.archive_member = archive_member;
+    }
+
+    /// Whether the entry is a directory on another file system than its root, which wasn't
+    /// descended into. See `TreeIter::one_file_system()`.
+    pub fn is_mount_point(&self) -> bool {
+        self.mount_point
+    }
+
+    /// Set whether the entry is a mount point.
+    pub fn set_mount_point(&mut self, mount_point: bool) {
+        self.mount_point = mount_point;
+    }
 }
 
 impl fmt::Debug for Entry {
@@ -50,33 +133,109 @@ impl fmt::Debug for Entry {
             .field("path", &self.path)
             .field("has_next_sibling", &self.has_next_sibling)
             .field("is_dir", &self.metadata.is_dir())
+            .field("ignored", &self.ignored)
+            .field("archive_member", &self.archive_member)
+            .field("mount_point", &self.mount_point)
             .finish()
     }
 }
 
-/// An iterator yielding only the entries in dir where `file_filter` returns true.
+/// A source of `Event`s describing a tree.
+///
+/// `TreeIter` walks the file system or any other `source::TreeSource`, and adapters like
+/// `watch::Recorder` pass its events on. All sources yield a final `CloseDir` for the root.
+pub trait EventSource: Iterator<Item=Result<Event, Box<Error>>> {
+    /// Number of entries rejected by the file filter so far.
+    fn num_filtered(&self) -> usize;
+
+    /// The source of the tree, e.g. to read the contents of its entries.
+    fn source(&self) -> Rc<TreeSource>;
+}
+
+/// The error yielded when the entries of a directory can't be read, e.g. for lack of permission.
+///
+/// The walk goes on with the next sibling of the directory, which is left out.
+#[derive(Debug)]
+pub struct ReadDirError {
+    entry: Entry,
+    error: io::Error,
+}
+
+impl ReadDirError {
+    fn new(entry: Entry, error: io::Error) -> Self {
+        ReadDirError {
+            entry: entry,
+            error: error,
+        }
+    }
+
+    /// Path of the directory, prefixed with the root.
+    pub fn path(&self) -> &Path {
+        self.entry.path()
+    }
+
+    /// The entry of the directory, as it would have been yielded with `Event::OpenDir`.
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// The kind of the underlying error, e.g. to tell a directory that vanished during the walk
+    /// from an unreadable one.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+}
+
+impl fmt::Display for ReadDirError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Failed to read dir '{}': {}", self.path().display(), self.error)
+    }
+}
+
+impl Error for ReadDirError {
+    fn description(&self) -> &str {
+        "failed to read dir"
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        Some(&self.error)
+    }
+}
+
+/// An iterator yielding only the entries in dir that `file_filter` doesn't exclude, along with
+/// their metadata and whether each entry is ignored. All entries of an ignored directory are
+/// ignored.
+///
+/// Rejected entries are counted in `num_filtered`, which is shared by all directories of a walk.
 struct FilteredDir {
     file_filter: Rc<FileFilter>,
-    dir: fs::ReadDir,
+    num_filtered: Rc<Cell<usize>>,
+    dir: DirEntries,
+    ignored: bool,
 }
 
 impl FilteredDir {
-    pub fn new<P>(path: P, file_filter: Rc<FileFilter>) -> Result<Self, Box<Error>> where
+    pub fn new<P>(source: &TreeSource,
+                  path: P,
+                  file_filter: Rc<FileFilter>,
+                  num_filtered: Rc<Cell<usize>>,
+                  ignored: bool)
+                  -> io::Result<Self> where
         P: AsRef<Path>,
     {
-        fs::read_dir(&path)
-            .map(|dir| {
-                FilteredDir {
-                    file_filter: file_filter,
-                    dir: dir,
-                }
-            })
-            .map_err(|err| From::from(format!("Failed to read dir '{}': {}", path.as_ref().display(), err)))
+        source.read_dir(path.as_ref()).map(|dir| {
+            FilteredDir {
+                file_filter: file_filter,
+                num_filtered: num_filtered,
+                dir: dir,
+                ignored: ignored,
+            }
+        })
     }
 }
 
 impl Iterator for FilteredDir {
-    type Item = Result<fs::DirEntry, Box<Error>>;
+    type Item = Result<(PathBuf, Metadata, bool), Box<Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -85,26 +244,31 @@ impl Iterator for FilteredDir {
                 None => return None,
             };
 
-            let entry = match result {
+            let (path, metadata) = match result {
                 Ok(entry) => entry,
                 Err(err) => return Some(Err(From::from(err))),
             };
 
-            let should_yield = match self.file_filter.filter(entry.path().as_path()) {
-                Ok(should_yield) => should_yield,
+            let verdict = match self.file_filter.verdict(&path, &metadata) {
+                Ok(verdict) => verdict,
                 Err(err) => return Some(Err(From::from(err))),
             };
 
-            if should_yield {
-                return Some(Ok(entry));
+            match verdict {
+                Verdict::Include => return Some(Ok((path, metadata, self.ignored))),
+                Verdict::Annotate => return Some(Ok((path, metadata, true))),
+                Verdict::Exclude => {},
             }
+
+            self.num_filtered.set(self.num_filtered.get() + 1);
         }
     }
 }
 
 /// A filtered recursive directory iterator.
 ///
-/// The iterator descends the tree depth first. This means that all of a directory's children
+/// The iterator walks the file system, or any other `source::TreeSource`. It descends the tree
+/// depth first. This means that all of a directory's children
 /// will immediately follow thier parent. This essentially mirrors the output of this program.
 ///
 /// # Example
@@ -134,8 +298,58 @@ impl Iterator for FilteredDir {
 /// File(d)
 /// ```
 pub struct TreeIter {
-    dir_stack: Vec<Peekable<FilteredDir>>,
+    source: Rc<TreeSource>,
+    dir_stack: Vec<DirState>,
     file_filter: Rc<FileFilter>,
+    num_filtered: Rc<Cell<usize>>,
+    file_limit: Option<usize>,
+    max_children: Option<usize>,
+    algorithm: Option<Algorithm>,
+    archive_nesting: Option<usize>,
+    /// The walk of the archive being expanded, whose events are yielded until it's exhausted.
+    archive: Option<Box<TreeIter>>,
+    /// The device of the root, if known.
+    root_dev: Option<u64>,
+    one_file_system: bool,
+}
+
+/// A filter judging entries that aren't on disk, like the members of an expanded archive, see
+/// `FileFilter::detached_verdict()`.
+struct DetachedFilter(Rc<FileFilter>);
+
+impl FileFilter for DetachedFilter {
+    fn filter(&self, path: &Path, metadata: &Metadata) -> Result<bool, Box<Error>> {
+        self.verdict(path, metadata).map(|verdict| verdict != Verdict::Exclude)
+    }
+
+    fn verdict(&self, path: &Path, metadata: &Metadata) -> Result<Verdict, Box<Error>> {
+        self.0.detached_verdict(path, metadata)
+    }
+
+    fn explain(&self, path: &Path, metadata: &Metadata) -> Option<String> {
+        self.0.explain(path, metadata)
+    }
+}
+
+/// A directory on the `TreeIter` stack along with the number of entries yielded from it so far,
+/// and the number of entries the source left out, which are reported once it's exhausted.
+struct DirState {
+    entries: Peekable<FilteredDir>,
+    /// Errors set aside while looking for the next sibling of an entry, yielded next.
+    errors: VecDeque<Box<Error>>,
+    num_yielded: usize,
+    num_elided: usize,
+}
+
+impl DirState {
+    fn new(dir: FilteredDir, num_elided: usize) -> Self {
+        DirState {
+            entries: dir.peekable(),
+            errors: VecDeque::new(),
+            num_yielded: 0,
+            num_elided: num_elided,
+        }
+    }
 }
 
 impl TreeIter {
@@ -144,76 +358,234 @@ impl TreeIter {
         P: AsRef<Path>,
         F: FileFilter + 'static
     {
-        let rc_filter = Rc::new(file_filter);
+        TreeIter::with_source(FsSource, path, file_filter)
+    }
 
-        fs::read_dir(path)
-            .map(|dir| {
-                let filtered = FilteredDir {
-                    file_filter: rc_filter.clone(),
-                    dir: dir,
-                };
-                TreeIter {
-                    dir_stack: vec![filtered.peekable()],
-                    file_filter: rc_filter,
-                }
-            })
+    /// Create a new iterator walking `source` from `path`.
+    pub fn with_source<S, P, F>(source: S,
+                                path: P,
+                                file_filter: F)
+                                -> Result<Self, Box<Error>> where
+        S: TreeSource + 'static,
+        P: AsRef<Path>,
+        F: FileFilter + 'static
+    {
+        TreeIter::walk(Rc::new(source),
+                       path.as_ref(),
+                       Rc::new(file_filter),
+                       Rc::new(Cell::new(0)),
+                       false)
+    }
+
+    /// Create an iterator walking `source` from `path`, sharing `file_filter` and the count of
+    /// filtered entries with another walk, e.g. of the tree holding an archive. All entries are
+    /// marked as ignored if `ignored` is set.
+    fn walk(source: Rc<TreeSource>,
+            path: &Path,
+            file_filter: Rc<FileFilter>,
+            num_filtered: Rc<Cell<usize>>,
+            ignored: bool)
+            -> Result<Self, Box<Error>> {
+        let root_dev = source.target_metadata(path).ok().map(|metadata| metadata.dev());
+        let dir = FilteredDir {
+            file_filter: file_filter.clone(),
+            num_filtered: num_filtered.clone(),
+            dir: try!(source.read_dir(path)),
+            ignored: ignored,
+        };
+        let num_elided = source.num_elided(path);
+
+        Ok(TreeIter {
+            source: source,
+            dir_stack: vec![DirState::new(dir, num_elided)],
+            file_filter: file_filter,
+            num_filtered: num_filtered,
+            file_limit: None,
+            max_children: None,
+            algorithm: None,
+            archive_nesting: None,
+            archive: None,
+            root_dev: root_dev,
+            one_file_system: false,
+        })
+    }
+
+    /// Do not descend into directories containing more than `limit` entries.
+    ///
+    /// Such directories are yielded as `Event::LimitedDir` instead of `Event::OpenDir`. The count
+    /// includes all entries in the directory, whether they would be filtered or not.
+    pub fn file_limit(&mut self, limit: usize) -> &mut Self {
+        self.file_limit = Some(limit);
+        self
+    }
+
+    /// Yield at most `max` entries from each directory.
+    ///
+    /// If a directory contains more entries, the rest are counted and reported with a single
+    /// `Event::Elided`.
+    pub fn max_children(&mut self, max: usize) -> &mut Self {
+        self.max_children = Some(max);
+        self
+    }
+
+    /// Compute the digest of each entry as it's walked, using `algorithm`.
+    ///
+    /// Files are read in chunks, so memory use doesn't depend on their size. Entries with a
+    /// digest from the source, and those of sources without contents, aren't hashed. See
+    /// `Entry::digest()`.
+    pub fn hash(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.algorithm = Some(algorithm);
+        self
+    }
+
+    /// Yield archives as directories holding their members, with archives within them expanded
+    /// too up to `nesting` levels deep. Zero expands only the archives found on disk.
+    ///
+    /// The archive itself is yielded as `Event::OpenDir`, although its metadata is that of a
+    /// file, and its members are marked, see `Entry::is_archive_member()`. Archives are
+    /// recognized by their file names, see `archive::Format::from_path()`, and listed as plain
+    /// files if they can't be read.
+    pub fn expand_archives(&mut self, nesting: usize) -> &mut Self {
+        self.archive_nesting = Some(nesting);
+        self
+    }
+
+    /// Do not descend into directories on other file systems than the root, e.g. `/proc` or
+    /// network shares mounted below it.
+    ///
+    /// Such directories are yielded as `Event::OpenDir` immediately followed by
+    /// `Event::CloseDir`, and marked as mount points, see `Entry::is_mount_point()`. File systems
+    /// are told apart by device id.
+    pub fn one_file_system(&mut self, one_file_system: bool) -> &mut Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Walk the archive at `entry` as a tree of its members, if it is one.
+    fn open_archive(&self, entry: &Entry) -> Option<TreeIter> {
+        let nesting = match self.archive_nesting {
+            Some(nesting) if entry.metadata.is_file() => nesting,
+            _ => return None,
+        };
+        let format = match Format::from_path(&entry.path) {
+            Some(format) => format,
+            None => return None,
+        };
+        let tree = self.source
+            .open(&entry.path)
             .map_err(From::from)
+            .and_then(|file| ArchiveTree::from_reader(file, entry.path.clone(), format, nesting));
+        let archive = tree.and_then(|tree| {
+            // Members aren't on disk, so filters mustn't look them up there
+            TreeIter::walk(Rc::new(tree.into_source()),
+                           &entry.path,
+                           Rc::new(DetachedFilter(self.file_filter.clone())),
+                           self.num_filtered.clone(),
+                           entry.ignored)
+        });
+
+        match archive {
+            Ok(mut archive) => {
+                archive.file_limit = self.file_limit;
+                archive.max_children = self.max_children;
+                Some(archive)
+            },
+            Err(_) => None,
+        }
+    }
+}
+
+impl EventSource for TreeIter {
+    fn num_filtered(&self) -> usize {
+        self.num_filtered.get()
+    }
+
+    fn source(&self) -> Rc<TreeSource> {
+        self.source.clone()
     }
 }
 
-fn has_next_sibling<T, E, I: Iterator<Item=Result<T, E>>>(dir: &mut Peekable<I>) -> bool {
+/// Number of entries of the directory at `path`, including those the source left out.
+fn count_entries(source: &TreeSource, path: &Path) -> io::Result<usize> {
+    source.read_dir(path).map(|dir| dir.count() + source.num_elided(path))
+}
+
+/// Whether `dir` has another entry, moving any errors before it to `errors`.
+fn has_next_sibling<T, E, I>(dir: &mut Peekable<I>, errors: &mut VecDeque<E>) -> bool
+    where I: Iterator<Item = Result<T, E>>
+{
     loop {
         match dir.peek() {
-            Some(result) => {
-                if result.is_ok() {
-                    return true;
-                }
-            },
-            None => {
-                return false;
-            }
+            Some(&Ok(_)) => return true,
+            Some(&Err(_)) => {},
+            None => return false,
+        }
+
+        if let Some(Err(err)) = dir.next() {
+            errors.push_back(err);
         }
     }
 }
 
-fn next_entry(dir: &mut Peekable<FilteredDir>) -> Option<Result<Entry, Box<Error>>> {
-    let entry = match dir.next() {
-        Some(Ok(entry)) => entry,
+fn next_entry(dir: &mut DirState) -> Option<Result<Entry, Box<Error>>> {
+    if let Some(err) = dir.errors.pop_front() {
+        return Some(Err(err));
+    }
+
+    let (path, metadata, ignored) = match dir.entries.next() {
+        Some(Ok(next)) => next,
         Some(Err(err)) => return Some(Err(From::from(err))),
         None => return None,
     };
 
-    let has_next_sibling = has_next_sibling(dir);
-    let metadata = match entry.metadata() {
-        Ok(metadata) => metadata,
-        Err(err) => return Some(Err(From::from(err))),
-    };
-    let path = entry.path();
+    let has_next_sibling = has_next_sibling(&mut dir.entries, &mut dir.errors);
 
-    Some(Ok(Entry {
-        path: path,
-        metadata: metadata,
-        has_next_sibling: has_next_sibling,
-    }))
+    Some(Ok(Entry::new(path, metadata, has_next_sibling, ignored)))
 }
 
 impl Iterator for TreeIter {
     type Item = Result<Event, Box<Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.archive.as_mut().and_then(Iterator::next) {
+            // The final `CloseDir` of the archive's walk closes the archive
+            return Some(event);
+        }
+
+        self.archive = None;
+
         let entry;
+        let max_children = self.max_children;
 
         loop {
             match self.dir_stack.as_mut_slice().last_mut() {
                 Some(dir) => {
-                    match next_entry(dir) {
-                        Some(Ok(the_entry)) => {
-                            entry = the_entry;
-                            break;
-                        },
-                        Some(Err(err)) => return Some(Err(err)),
-                        // Top dir is empty, go down a level by falling through
-                        None => {},
+                    if max_children.map_or(false, |max| dir.num_yielded >= max) {
+                        // Exhaust the directory, counting what is left along with what the
+                        // source left out. The next call will find it empty and close it.
+                        let num_elided = dir.entries.by_ref().filter(Result::is_ok).count() +
+                                         mem::replace(&mut dir.num_elided, 0);
+
+                        if num_elided > 0 {
+                            return Some(Ok(Event::Elided(num_elided)));
+                        }
+                    } else {
+                        match next_entry(dir) {
+                            Some(Ok(mut the_entry)) => {
+                                dir.num_yielded += 1;
+                                // Entries left out by the source are reported last
+                                the_entry.has_next_sibling |= dir.num_elided > 0;
+                                entry = the_entry;
+                                break;
+                            },
+                            Some(Err(err)) => return Some(Err(err)),
+                            None if dir.num_elided > 0 => {
+                                let num_elided = mem::replace(&mut dir.num_elided, 0);
+                                return Some(Ok(Event::Elided(num_elided)));
+                            },
+                            // Top dir is empty, go down a level by falling through
+                            None => {},
+                        }
                     }
                 },
                 // We reached top of dir stack
@@ -225,15 +597,352 @@ impl Iterator for TreeIter {
             return Some(Ok(Event::CloseDir));
         };
 
-        if entry.metadata.is_dir() {
-            match FilteredDir::new(&entry.path, self.file_filter.clone()) {
-                Ok(dir) => self.dir_stack.push(dir.peekable()),
-                Err(err) => return Some(Err(From::from(err))),
+        let mut entry = entry;
+        self.source.annotate(&mut entry);
+
+        if entry.metadata.is_dir() || self.source.is_expanded(&entry.path) {
+            let on_other_dev = self.root_dev.map_or(false, |dev| dev != entry.metadata.dev());
+
+            if self.one_file_system && on_other_dev {
+                entry.set_mount_point(true);
+
+                // Close it again right away
+                let empty = FilteredDir {
+                    file_filter: self.file_filter.clone(),
+                    num_filtered: self.num_filtered.clone(),
+                    dir: Box::new(iter::empty()),
+                    ignored: entry.ignored,
+                };
+                self.dir_stack.push(DirState::new(empty, 0));
+
+                return Some(Ok(Event::OpenDir(entry)));
+            }
+
+            if let Some(num_entries) = self.source.limited_dir(&entry.path) {
+                return Some(Ok(Event::LimitedDir(entry, num_entries)));
+            }
+
+            if let Some(limit) = self.file_limit {
+                match count_entries(&*self.source, &entry.path) {
+                    Ok(num_entries) if num_entries > limit => {
+                        return Some(Ok(Event::LimitedDir(entry, num_entries)));
+                    },
+                    Ok(_) => {},
+                    Err(err) => return Some(Err(From::from(ReadDirError::new(entry, err)))),
+                }
+            }
+
+            let dir = FilteredDir::new(&*self.source,
+                                       &entry.path,
+                                       self.file_filter.clone(),
+                                       self.num_filtered.clone(),
+                                       entry.ignored);
+
+            match dir {
+                Ok(dir) => {
+                    let num_elided = self.source.num_elided(&entry.path);
+                    self.dir_stack.push(DirState::new(dir, num_elided));
+                },
+                Err(err) => return Some(Err(From::from(ReadDirError::new(entry, err)))),
             };
 
+            Some(Ok(Event::OpenDir(entry)))
+        } else if let Some(archive) = self.open_archive(&entry) {
+            self.archive = Some(Box::new(archive));
             Some(Ok(Event::OpenDir(entry)))
         } else {
+            let hash = entry.digest.is_none() && self.source.has_contents();
+
+            if let (true, Some(algorithm)) = (hash, self.algorithm) {
+                match hash::source_digest(&*self.source, &entry.path, &entry.metadata, algorithm) {
+                    Ok(Some(digest)) => entry.set_digest(digest),
+                    Ok(None) => {},
+                    Err(err) => {
+                        return Some(Err(From::from(format!("Failed to hash '{}': {}",
+                                                           entry.path.display(),
+                                                           err))))
+                    },
+                }
+            }
+
             Some(Ok(Event::File(entry)))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate git2;
+    extern crate tar;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+    use std::process;
+    use super::*;
+    use super::super::filters::{self, FilterAggregate};
+    use super::super::hash::{self, Algorithm};
+    use super::super::metadata::FileType;
+    use super::super::source::MemorySource;
+
+    fn include_all(_path: &Path, _metadata: &Metadata) -> Result<bool, Box<Error>> {
+        Ok(true)
+    }
+
+    /// The events of `tree_iter`, one line each, with entries given by path.
+    fn events(tree_iter: &mut TreeIter) -> Vec<String> {
+        tree_iter
+            .map(|event| match event.unwrap() {
+                Event::File(entry) => format!("File({})", entry.path().display()),
+                Event::OpenDir(entry) => format!("OpenDir({})", entry.path().display()),
+                Event::CloseDir => String::from("CloseDir"),
+                Event::LimitedDir(entry, count) => {
+                    format!("LimitedDir({}, {})", entry.path().display(), count)
+                },
+                Event::Elided(count) => format!("Elided({})", count),
+            })
+            .collect()
+    }
+
+    fn example() -> MemorySource {
+        MemorySource::parse(".", "
+            a
+            b/1
+            b/2
+            c/
+            d
+        ")
+    }
+
+    #[test]
+    fn walks_depth_first() {
+        let mut tree_iter = TreeIter::with_source(example(), ".", include_all).unwrap();
+
+        assert_eq!(events(&mut tree_iter),
+                   ["File(./a)", "OpenDir(./b)", "File(./b/1)", "File(./b/2)", "CloseDir",
+                    "OpenDir(./c)", "CloseDir", "File(./d)", "CloseDir"]);
+    }
+
+    #[test]
+    fn has_next_sibling() {
+        let tree_iter = TreeIter::with_source(example(), ".", include_all).unwrap();
+        let siblings: Vec<_> = tree_iter
+            .filter_map(|event| match event.unwrap() {
+                Event::File(entry) | Event::OpenDir(entry) => {
+                    Some((entry.file_name().into_owned(), entry.has_next_sibling()))
+                },
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(siblings,
+                   [(String::from("a"), true),
+                    (String::from("b"), true),
+                    (String::from("1"), true),
+                    (String::from("2"), false),
+                    (String::from("c"), true),
+                    (String::from("d"), false)]);
+    }
+
+    #[test]
+    fn file_limit() {
+        let mut tree_iter = TreeIter::with_source(example(), ".", include_all).unwrap();
+        tree_iter.file_limit(1);
+
+        assert_eq!(events(&mut tree_iter),
+                   ["File(./a)", "LimitedDir(./b, 2)", "OpenDir(./c)", "CloseDir", "File(./d)",
+                    "CloseDir"]);
+    }
+
+    #[test]
+    fn max_children() {
+        let mut tree_iter = TreeIter::with_source(example(), ".", include_all).unwrap();
+        tree_iter.max_children(2);
+
+        assert_eq!(events(&mut tree_iter),
+                   ["File(./a)", "OpenDir(./b)", "File(./b/1)", "File(./b/2)", "CloseDir",
+                    "Elided(2)", "CloseDir"]);
+    }
+
+    #[test]
+    fn filters_and_counts() {
+        let source = MemorySource::parse(".", "
+            .hidden/file
+            .profile
+            visible
+        ");
+        let mut tree_iter = TreeIter::with_source(source, ".", filters::filter_hidden_files)
+            .unwrap();
+
+        assert_eq!(events(&mut tree_iter), ["File(./visible)", "CloseDir"]);
+        assert_eq!(tree_iter.num_filtered(), 2);
+    }
+
+    #[test]
+    fn annotated_dirs_are_ignored_throughout() {
+        let mut filters = FilterAggregate::default();
+        filters.push_annotating("hidden", filters::filter_hidden_files);
+
+        let source = MemorySource::parse(".", "
+            .git/config
+            src
+        ");
+        let ignored: Vec<_> = TreeIter::with_source(source, ".", filters)
+            .unwrap()
+            .filter_map(|event| match event.unwrap() {
+                Event::File(entry) | Event::OpenDir(entry) => {
+                    Some((entry.path().to_path_buf(), entry.is_ignored()))
+                },
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(ignored,
+                   [(PathBuf::from("./.git"), true),
+                    (PathBuf::from("./.git/config"), true),
+                    (PathBuf::from("./src"), false)]);
+    }
+
+    #[test]
+    fn unreadable_dir_is_an_error() {
+        let mut source = example();
+        source.fail("b");
+
+        let mut tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+        assert!(tree_iter.next().unwrap().is_ok());
+
+        let err = tree_iter.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Failed to read dir './b': Permission denied");
+
+        let err = err.downcast::<ReadDirError>().unwrap();
+        assert_eq!(err.path(), Path::new("./b"));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn failing_entry_is_an_error() {
+        let mut source = MemorySource::parse(".", "
+            a
+            b
+            c
+        ");
+        source.fail("b");
+
+        let mut tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+        let mut next = || tree_iter.next().unwrap();
+
+        match next().unwrap() {
+            Event::File(entry) => assert!(entry.has_next_sibling()),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(next().unwrap_err().to_string(), "No such file or directory");
+
+        match next().unwrap() {
+            Event::File(entry) => assert!(!entry.has_next_sibling()),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(next().is_ok());
+        assert!(tree_iter.next().is_none());
+    }
+
+    #[test]
+    fn missing_root_is_an_error() {
+        assert!(TreeIter::with_source(example(), "./missing", include_all).is_err());
+    }
+
+    #[test]
+    fn hashes_files_and_symlinks() {
+        let source = MemorySource::parse(".", "
+            dir/
+            file = contents
+            link -> file
+        ");
+        let mut tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+        tree_iter.hash(Algorithm::Sha256);
+
+        let digests: Vec<_> = tree_iter
+            .filter_map(|event| match event.unwrap() {
+                Event::File(entry) | Event::OpenDir(entry) => {
+                    Some(entry.digest().map(hash::to_hex))
+                },
+                _ => None,
+            })
+            .collect();
+
+        let expected = |data: &[u8]| {
+            let mut hasher = hash::Hasher::new(Algorithm::Sha256);
+            hasher.update(data);
+            Some(hash::to_hex(&hasher.finish()))
+        };
+
+        assert_eq!(digests, [None, expected(b"contents"), expected(b"file")]);
+    }
+
+    #[test]
+    fn one_file_system() {
+        let mut source = example();
+        let mut metadata = Metadata::new(FileType::Dir);
+        metadata.dev = 1;
+        source.entry("mnt", metadata, b"").file("mnt/file", b"");
+
+        let mut tree_iter = TreeIter::with_source(source, ".", include_all).unwrap();
+        tree_iter.one_file_system(true);
+
+        let mut mount_points = Vec::new();
+        let events: Vec<_> = tree_iter
+            .map(|event| match event.unwrap() {
+                Event::OpenDir(entry) => {
+                    if entry.is_mount_point() {
+                        mount_points.push(entry.path().to_path_buf());
+                    }
+                    format!("OpenDir({})", entry.path().display())
+                },
+                Event::CloseDir => String::from("CloseDir"),
+                _ => String::from("_"),
+            })
+            .collect();
+
+        assert_eq!(events,
+                   ["_", "OpenDir(./b)", "_", "_", "CloseDir", "OpenDir(./c)", "CloseDir", "_",
+                    "OpenDir(./mnt)", "CloseDir", "CloseDir"]);
+        assert_eq!(mount_points, [PathBuf::from("./mnt")]);
+    }
+
+    #[test]
+    fn expand_archives_in_git_repository() {
+        let dir = env::temp_dir().join(format!("oak-tree-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        git2::Repository::init(&dir).unwrap();
+        File::create(dir.join(".gitignore")).unwrap().write_all(b"ignored\n").unwrap();
+
+        let mut builder = tar::Builder::new(File::create(dir.join("archive.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder.append_data(&mut header, "ignored", &b"data"[..]).unwrap();
+        builder.append_data(&mut header, "member", &b"data"[..]).unwrap();
+        builder.into_inner().unwrap();
+
+        let mut filters = FilterAggregate::default();
+        filters.push_named("hidden", filters::filter_hidden_files);
+        filters.push_named("gitignore", filters::GitignoreFilter::new(&dir).unwrap().unwrap());
+
+        let mut tree_iter = TreeIter::new(&dir, filters).unwrap();
+        tree_iter.expand_archives(0);
+        let events = events(&mut tree_iter);
+        let _ = fs::remove_dir_all(&dir);
+
+        // Members can't be looked up in the repository, so they're all included
+        let archive = dir.join("archive.tar");
+        assert_eq!(events,
+                   [format!("OpenDir({})", archive.display()),
+                    format!("File({})", archive.join("ignored").display()),
+                    format!("File({})", archive.join("member").display()),
+                    String::from("CloseDir"),
+                    String::from("CloseDir")]);
+    }
+}
@@ -1,9 +1,9 @@
 //! Contains the `TreeProcessor` trait.
 
 use std::error::Error;
-use super::tree::{TreeIter, Entry, Event};
+use super::tree::{EventSource, Entry, Event, ReadDirError};
 
-/// A generic trait for processing the output of `TreeIter`.
+/// A generic trait for processing the output of `TreeIter` or any other `EventSource`.
 pub trait TreeProcessor {
     /// Called for each `OpenDir` event.
     fn open_dir(&mut self, entry: &Entry);
@@ -12,21 +12,54 @@ pub trait TreeProcessor {
     /// Called for each `File` event.
     fn file(&mut self, entry: &Entry);
 
-    /// Iterates thorugh a `TreeIter`, delegating each event to its respective method.
-    fn process(&mut self, tree: &mut TreeIter) -> Option<Box<Error>> {
-        for result in tree {
+    /// Called for each `LimitedDir` event.
+    ///
+    /// By default the directory is treated as if it were empty.
+    fn limited_dir(&mut self, entry: &Entry, _num_entries: usize) {
+        self.open_dir(entry);
+        self.close_dir();
+    }
+
+    /// Called for each `Elided` event. Does nothing by default.
+    fn elided(&mut self, _num_entries: usize) {}
+
+    /// Called for each directory whose entries can't be read, returning whether to go on with
+    /// the rest of the tree. By default the walk stops, and `process()` returns the error.
+    fn unreadable_dir(&mut self, _error: &ReadDirError) -> bool {
+        false
+    }
+
+    /// Called once after the last event, if the whole tree was processed without errors. Does
+    /// nothing by default.
+    fn finish(&mut self, _tree: &EventSource) {}
+
+    /// Iterates thorugh an `EventSource`, delegating each event to its respective method.
+    fn process(&mut self, tree: &mut EventSource) -> Option<Box<Error>> {
+        while let Some(result) = tree.next() {
             match result {
                 Ok(event) => {
                     match event {
                         Event::OpenDir(ref entry) => self.open_dir(entry),
                         Event::File(ref entry) => self.file(entry),
                         Event::CloseDir => self.close_dir(),
+                        Event::LimitedDir(ref entry, num_entries) => {
+                            self.limited_dir(entry, num_entries)
+                        },
+                        Event::Elided(num_entries) => self.elided(num_entries),
                     };
                 },
-                Err(err) => return Some(err),
+                Err(err) => {
+                    let go_on = err.downcast_ref::<ReadDirError>()
+                        .map_or(false, |err| self.unreadable_dir(err));
+
+                    if !go_on {
+                        return Some(err);
+                    }
+                },
             };
         };
 
+        self.finish(tree);
         None
     }
 }
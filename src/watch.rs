@@ -0,0 +1,216 @@
+//! Watching a tree for changes.
+//!
+//! `Watcher` uses inotify to wait for entries to be created, removed or renamed in a set of
+//! directories. Inotify watches aren't recursive, so every directory of interest is added
+//! individually, typically those seen while walking the tree with `Recorder`.
+
+extern crate inotify;
+extern crate libc;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use self::inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use super::source::TreeSource;
+use super::tree::{Event, EventSource, ReadDirError};
+
+/// Size of the buffer inotify events are read into, enough for a few hundred events.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// How an entry changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Created, or renamed to this name.
+    Created,
+    /// Removed, or renamed from this name.
+    Removed,
+}
+
+/// A change to an entry in a watched directory.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// Shared by the removal and creation making up a rename within the watched directories,
+    /// otherwise zero.
+    pub cookie: u32,
+}
+
+/// Waits for changes to the entries of a set of directories.
+pub struct Watcher {
+    inotify: Inotify,
+    /// The path of each watched directory, as passed to `watch_dir()`.
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    /// Create a watcher without any watched directories.
+    pub fn new() -> io::Result<Self> {
+        Ok(Watcher {
+            inotify: try!(Inotify::init()),
+            dirs: HashMap::new(),
+            buffer: vec![0; BUFFER_SIZE],
+        })
+    }
+
+    /// Watch the entries of the directory at `path`. Watching a directory again has no effect.
+    ///
+    /// Directories are no longer watched once they're removed.
+    pub fn watch_dir(&mut self, path: &Path) -> io::Result<()> {
+        let mask = WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM |
+                   WatchMask::MOVED_TO;
+        let wd = try!(self.inotify.watches().add(path, mask));
+        self.dirs.insert(wd, path.to_path_buf());
+        Ok(())
+    }
+
+    /// Block until entries change, and return the changes.
+    ///
+    /// After the first change, changes are collected until none have happened for `debounce`,
+    /// so that a burst of changes, e.g. from a build, is returned at once.
+    pub fn wait(&mut self, debounce: Duration) -> io::Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        try!(self.read_changes(&mut changes));
+
+        while try!(self.poll(debounce)) {
+            try!(self.read_changes(&mut changes));
+        }
+
+        Ok(changes)
+    }
+
+    /// Read the pending events, blocking until there are some.
+    fn read_changes(&mut self, changes: &mut Vec<Change>) -> io::Result<()> {
+        let events = try!(self.inotify.read_events_blocking(&mut self.buffer));
+
+        for event in events {
+            if event.mask.contains(EventMask::IGNORED) {
+                // The directory was removed, or otherwise stopped being watched
+                self.dirs.remove(&event.wd);
+                continue;
+            }
+
+            let (dir, name) = match (self.dirs.get(&event.wd), event.name) {
+                (Some(dir), Some(name)) => (dir, name),
+                _ => continue,
+            };
+
+            let kind = if event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                ChangeKind::Created
+            } else {
+                ChangeKind::Removed
+            };
+
+            changes.push(Change {
+                path: dir.join(name),
+                kind: kind,
+                cookie: event.cookie,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Whether there are events to read within `timeout`.
+    fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_secs() as libc::c_int * 1000 +
+                         timeout.subsec_nanos() as libc::c_int / 1_000_000;
+
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+}
+
+/// An `EventSource` adapter recording the paths of the entries passing through, so that their
+/// directories can be watched and changes to them recognized.
+///
+/// Errors about entries that vanished during the walk, e.g. removed by a build, are skipped. Such
+/// entries are left out, as if they had been removed before the walk.
+pub struct Recorder<'a> {
+    source: &'a mut EventSource,
+    dirs: Vec<PathBuf>,
+    paths: HashSet<PathBuf>,
+}
+
+impl<'a> Recorder<'a> {
+    /// Record the events of `source`.
+    pub fn new(source: &'a mut EventSource) -> Self {
+        Recorder {
+            source: source,
+            dirs: Vec::new(),
+            paths: HashSet::new(),
+        }
+    }
+
+    /// The directories that were descended into so far.
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Consume the recorder, returning the paths of all entries seen.
+    pub fn into_paths(self) -> HashSet<PathBuf> {
+        self.paths
+    }
+}
+
+impl<'a> EventSource for Recorder<'a> {
+    fn num_filtered(&self) -> usize {
+        self.source.num_filtered()
+    }
+
+    fn source(&self) -> Rc<TreeSource> {
+        self.source.source()
+    }
+}
+
+impl<'a> Iterator for Recorder<'a> {
+    type Item = Result<Event, Box<Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = loop {
+            match self.source.next() {
+                Some(Err(ref err)) if vanished(&**err) => {},
+                result => break result,
+            }
+        };
+
+        if let Some(Ok(ref event)) = result {
+            match *event {
+                Event::OpenDir(ref entry) => {
+                    self.dirs.push(entry.path().to_path_buf());
+                    self.paths.insert(entry.path().to_path_buf());
+                },
+                Event::File(ref entry) | Event::LimitedDir(ref entry, _) => {
+                    self.paths.insert(entry.path().to_path_buf());
+                },
+                Event::CloseDir | Event::Elided(_) => {},
+            }
+        }
+
+        result
+    }
+}
+
+/// Whether `err` is about an entry that no longer exists.
+fn vanished(err: &(Error + 'static)) -> bool {
+    let kind = match (err.downcast_ref::<ReadDirError>(), err.downcast_ref::<io::Error>()) {
+        (Some(err), _) => err.kind(),
+        (_, Some(err)) => err.kind(),
+        _ => return false,
+    };
+
+    kind == io::ErrorKind::NotFound
+}
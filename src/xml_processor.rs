@@ -0,0 +1,188 @@
+//! `XmlProcessor` and supporting types.
+//!
+//! The output is compatible with the XML format of the classic `tree` utility (`tree -X`).
+
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use super::tree_processor::TreeProcessor;
+use super::tree::{Entry, EventSource};
+use super::columns::{self, DEFAULT_TIME_FORMAT};
+
+/// Builder for `XmlProcessor`.
+pub struct XmlProcessorBuilder {
+    root: PathBuf,
+    time_format: String,
+}
+
+impl XmlProcessorBuilder {
+    /// Create a new builder.
+    pub fn new(root: PathBuf) -> Self {
+        XmlProcessorBuilder {
+            root: root,
+            time_format: From::from(DEFAULT_TIME_FORMAT),
+        }
+    }
+
+    /// Set the `strftime` style format of the `time` attributes.
+    pub fn time_format(&mut self, format: String) -> &mut Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Build an `XmlProcessor`.
+    ///
+    /// This method also prints the XML declaration and the root directory element.
+    pub fn build(&self) -> XmlProcessor {
+        println!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        println!("<tree>");
+        println!("  <directory {}>", name_attributes(self.root.as_os_str()));
+
+        XmlProcessor {
+            time_format: self.time_format.clone(),
+            depth: 2,
+            num_dirs: 0,
+            num_files: 0,
+        }
+    }
+}
+
+/// A `TreeProcessor` printing the tree as XML.
+///
+/// # Example
+///
+/// ```text
+/// <?xml version="1.0" encoding="UTF-8"?>
+/// <tree>
+///   <directory name=".">
+///     <directory name="b" size="4096" time="Oct 19 12:00">
+///       <file name="1" size="12" time="Oct 19 12:00"/>
+///     </directory>
+///   </directory>
+///   <report>
+///     <directories>1</directories>
+///     <files>1</files>
+///   </report>
+/// </tree>
+/// ```
+pub struct XmlProcessor {
+    time_format: String,
+    depth: usize,
+    num_dirs: usize,
+    num_files: usize,
+}
+
+impl XmlProcessor {
+    fn indent(&self) -> String {
+        "  ".repeat(self.depth)
+    }
+
+    /// The attributes common to all elements representing entries.
+    fn attributes(&self, entry: &Entry) -> String {
+        let metadata = entry.metadata();
+        let mut attributes = format!("{} size=\"{}\" time=\"{}\"",
+                                     name_attributes(entry.path().file_name().unwrap_or_default()),
+                                     metadata.len(),
+                                     escape(&columns::format_time(metadata.mtime(),
+                                                                  &self.time_format)));
+
+        if entry.is_ignored() {
+            attributes.push_str(" ignored=\"true\"");
+        }
+
+        attributes
+    }
+}
+
+impl TreeProcessor for XmlProcessor {
+    fn open_dir(&mut self, entry: &Entry) {
+        println!("{}<directory {}>", self.indent(), self.attributes(entry));
+        self.depth += 1;
+        self.num_dirs += 1;
+    }
+
+    fn close_dir(&mut self) {
+        self.depth -= 1;
+        println!("{}</directory>", self.indent());
+    }
+
+    fn file(&mut self, entry: &Entry) {
+        println!("{}<file {}/>", self.indent(), self.attributes(entry));
+        self.num_files += 1;
+    }
+
+    fn limited_dir(&mut self, entry: &Entry, num_entries: usize) {
+        println!("{}<directory {}>", self.indent(), self.attributes(entry));
+        println!("{}  <error>{} entries exceeds filelimit, not opening dir</error>",
+                 self.indent(),
+                 num_entries);
+        println!("{}</directory>", self.indent());
+        self.num_dirs += 1;
+    }
+
+    fn elided(&mut self, num_entries: usize) {
+        println!("{}<more count=\"{}\"/>", self.indent(), num_entries);
+    }
+
+    fn finish(&mut self, _tree: &EventSource) {
+        println!("  <report>");
+        println!("    <directories>{}</directories>", self.num_dirs);
+        println!("    <files>{}</files>", self.num_files);
+        println!("  </report>");
+        println!("</tree>");
+    }
+}
+
+/// Whether `c` may appear in an XML 1.0 document.
+fn is_xml_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => true,
+        '\u{20}'...'\u{D7FF}' | '\u{E000}'...'\u{FFFD}' | '\u{10000}'...'\u{10FFFF}' => true,
+        _ => false,
+    }
+}
+
+/// Escape the characters with special meaning in XML.
+///
+/// Characters not allowed in XML at all are replaced with U+FFFD.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if is_xml_char(c) => escaped.push(c),
+            _ => escaped.push('\u{FFFD}'),
+        }
+    }
+
+    escaped
+}
+
+/// The `name` attribute for a file name.
+///
+/// Names that can't be represented exactly in XML, because they aren't valid UTF-8 or contain
+/// control characters, get a lossy `name` along with the raw bytes percent-encoded in `rawname`.
+fn name_attributes(name: &OsStr) -> String {
+    match name.to_str() {
+        Some(name) if name.chars().all(is_xml_char) => format!("name=\"{}\"", escape(name)),
+        _ => {
+            let raw: String = name.as_bytes()
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+                        (byte as char).to_string()
+                    } else {
+                        format!("%{:02X}", byte)
+                    }
+                })
+                .collect();
+
+            format!("name=\"{}\" rawname=\"{}\"", escape(&name.to_string_lossy()), raw)
+        },
+    }
+}
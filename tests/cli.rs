@@ -0,0 +1,170 @@
+//! Golden output tests of the command line interface.
+//!
+//! Each test runs oak on a JSON listing in `tests/fixtures`, so that the output doesn't depend
+//! on the file system, and compares it with a file in `tests/golden`. Set `UPDATE_GOLDEN=1` to
+//! write the current output to the golden files instead, and review the difference.
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+const FIXTURE: &'static str = "fixtures/project.json";
+
+/// Run oak on the main fixture with `args`, and compare the output with the golden file `name`.
+fn check(name: &str, args: &[&str]) {
+    check_fixture(name, FIXTURE, args);
+}
+
+/// Like `check()`, on the listing at `fixture`.
+fn check_fixture(name: &str, fixture: &str, args: &[&str]) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let output = Command::new(env!("CARGO_BIN_EXE_oak"))
+        .current_dir(&dir)
+        .env("TZ", "UTC")
+        .arg("--from-json")
+        .arg(fixture)
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(),
+            "oak {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr));
+
+    let path = dir.join("golden").join(format!("{}.txt", name));
+    let actual = String::from_utf8(output.stdout).unwrap();
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        File::create(&path).unwrap().write_all(actual.as_bytes()).unwrap();
+        return;
+    }
+
+    let mut expected = String::new();
+    File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut expected))
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", path.display(), err));
+
+    assert!(actual == expected,
+            "Output of oak {} differs from {}:\n{}",
+            args.join(" "),
+            path.display(),
+            actual);
+}
+
+#[test]
+fn tree() {
+    check("tree", &[]);
+}
+
+#[test]
+fn show_hidden() {
+    check("show_hidden", &["-a"]);
+}
+
+#[test]
+fn show_ignored() {
+    check("show_ignored", &["--show-ignored"]);
+}
+
+#[test]
+fn only_dirs() {
+    check("only_dirs", &["-d"]);
+}
+
+#[test]
+fn columns() {
+    check("columns", &["-p", "-D", "-F"]);
+}
+
+#[test]
+fn timefmt() {
+    check("timefmt", &["--timefmt", "%Y-%m-%d %H:%M", "-d"]);
+}
+
+#[test]
+fn compact() {
+    check("compact", &["--compact"]);
+}
+
+#[test]
+fn compact_mount_points() {
+    check_fixture("compact_mount_points", "fixtures/mounts.json", &["--compact", "-x"]);
+}
+
+#[test]
+fn compact_ignored() {
+    check_fixture("compact_ignored", "fixtures/ignored.json", &["--compact", "--show-ignored"]);
+}
+
+#[test]
+fn filelimit() {
+    check("filelimit", &["--filelimit", "3"]);
+}
+
+#[test]
+fn max_children() {
+    check("max_children", &["--max-children", "2"]);
+}
+
+#[test]
+fn include_glob() {
+    check("include_glob", &["-P", "**/src", "-P", "**/src/*.rs"]);
+}
+
+#[test]
+fn exclude_glob() {
+    check("exclude_glob", &["-I", "*.o", "-I", "scripts"]);
+}
+
+#[test]
+fn list() {
+    check("list", &["--list"]);
+}
+
+#[test]
+fn markdown() {
+    check("markdown", &["--format", "markdown", "--code"]);
+}
+
+#[test]
+fn markdown_base_href() {
+    check("markdown_base_href", &["--format", "markdown", "--base-href", "https://example.com/"]);
+}
+
+#[test]
+fn html() {
+    check("html", &["--format", "html", "--base-href", "https://example.com/"]);
+}
+
+#[test]
+fn xml() {
+    check("xml", &["--format", "xml"]);
+}
+
+#[test]
+fn dot() {
+    check("dot", &["--format", "dot", "--size-weighted"]);
+}
+
+#[test]
+fn csv() {
+    check("csv", &["--format", "csv", "--fields", "path,type,size,mode,depth"]);
+}
+
+#[test]
+fn tsv() {
+    check("tsv", &["--format", "tsv", "--fields", "path,size,mtime"]);
+}
+
+#[test]
+fn json() {
+    check("json", &["--format", "json"]);
+}
+
+#[test]
+fn explain() {
+    check("explain", &["--explain", "project/.env"]);
+}